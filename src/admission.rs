@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use anyhow::Context;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{CorpusRow, Tokenizer};
+
+/// Rules that decide which entries of a corpus are admitted into the search tree.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AdmissionRules {
+    /// The minimum number of characters of a search term.
+    pub min_chars: Option<usize>,
+    /// The minimum number of tokens of a search term.
+    pub min_tokens: Option<usize>,
+    /// If true, search terms consisting only of numeric tokens are rejected. Defaults to false.
+    pub reject_numeric: Option<bool>,
+    /// If true, search terms consisting only of filter list words are rejected. Defaults to false.
+    pub reject_filter_words: Option<bool>,
+    /// If given, search terms must match at least one of these regular expressions.
+    pub include_terms: Option<Vec<String>>,
+    /// Search terms matching any of these regular expressions are rejected.
+    pub exclude_terms: Option<Vec<String>>,
+    /// If given, labels must match at least one of these regular expressions.
+    pub include_labels: Option<Vec<String>>,
+    /// Labels matching any of these regular expressions are rejected.
+    pub exclude_labels: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AdmissionRule {
+    MinChars,
+    MinTokens,
+    Numeric,
+    FilterWords,
+    IncludeTerms,
+    ExcludeTerms,
+    IncludeLabels,
+    ExcludeLabels,
+}
+
+impl Display for AdmissionRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MinChars => write!(f, "min_chars"),
+            Self::MinTokens => write!(f, "min_tokens"),
+            Self::Numeric => write!(f, "reject_numeric"),
+            Self::FilterWords => write!(f, "reject_filter_words"),
+            Self::IncludeTerms => write!(f, "include_terms"),
+            Self::ExcludeTerms => write!(f, "exclude_terms"),
+            Self::IncludeLabels => write!(f, "include_labels"),
+            Self::ExcludeLabels => write!(f, "exclude_labels"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RobustAdmissionRules {
+    pub min_chars: usize,
+    pub min_tokens: usize,
+    pub reject_numeric: bool,
+    pub reject_filter_words: bool,
+    pub include_terms: Option<Vec<Regex>>,
+    pub exclude_terms: Vec<Regex>,
+    pub include_labels: Option<Vec<Regex>>,
+    pub exclude_labels: Vec<Regex>,
+}
+
+fn compile_patterns(patterns: Option<Vec<String>>) -> anyhow::Result<Option<Vec<Regex>>> {
+    patterns
+        .map(|patterns| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Regex::new(pattern).context(format!("Invalid admission pattern '{pattern}'"))
+                })
+                .collect::<anyhow::Result<Vec<Regex>>>()
+        })
+        .transpose()
+}
+
+impl TryFrom<AdmissionRules> for RobustAdmissionRules {
+    type Error = anyhow::Error;
+
+    fn try_from(rules: AdmissionRules) -> Result<Self, Self::Error> {
+        Ok(RobustAdmissionRules {
+            min_chars: rules.min_chars.unwrap_or(0),
+            min_tokens: rules.min_tokens.unwrap_or(0),
+            reject_numeric: rules.reject_numeric.unwrap_or(false),
+            reject_filter_words: rules.reject_filter_words.unwrap_or(false),
+            include_terms: compile_patterns(rules.include_terms)?,
+            exclude_terms: compile_patterns(rules.exclude_terms)?.unwrap_or_default(),
+            include_labels: compile_patterns(rules.include_labels)?,
+            exclude_labels: compile_patterns(rules.exclude_labels)?.unwrap_or_default(),
+        })
+    }
+}
+
+impl RobustAdmissionRules {
+    /// Returns the first rule the given entry violates, if any.
+    pub fn check(
+        &self,
+        search_term: &str,
+        label: &str,
+        segments: &[String],
+        filter_words: &HashSet<String>,
+    ) -> Option<AdmissionRule> {
+        if search_term.chars().count() < self.min_chars {
+            return Some(AdmissionRule::MinChars);
+        }
+        if segments.len() < self.min_tokens {
+            return Some(AdmissionRule::MinTokens);
+        }
+        if self.reject_numeric
+            && segments
+                .iter()
+                .all(|segment| segment.chars().all(char::is_numeric))
+        {
+            return Some(AdmissionRule::Numeric);
+        }
+        if self.reject_filter_words
+            && segments
+                .iter()
+                .all(|segment| filter_words.contains(segment))
+        {
+            return Some(AdmissionRule::FilterWords);
+        }
+        if let Some(include_terms) = &self.include_terms {
+            if !include_terms.iter().any(|re| re.is_match(search_term)) {
+                return Some(AdmissionRule::IncludeTerms);
+            }
+        }
+        if self.exclude_terms.iter().any(|re| re.is_match(search_term)) {
+            return Some(AdmissionRule::ExcludeTerms);
+        }
+        if let Some(include_labels) = &self.include_labels {
+            if !include_labels.iter().any(|re| re.is_match(label)) {
+                return Some(AdmissionRule::IncludeLabels);
+            }
+        }
+        if self.exclude_labels.iter().any(|re| re.is_match(label)) {
+            return Some(AdmissionRule::ExcludeLabels);
+        }
+        None
+    }
+
+    /// Removes all entries that violate any of the rules and logs the number of rejected entries
+    /// per rule.
+    pub fn apply(
+        &self,
+        entries: Vec<CorpusRow>,
+        tokenizer: &Tokenizer,
+        filter_list: &Option<Vec<String>>,
+    ) -> Vec<CorpusRow> {
+        let filter_words: HashSet<String> =
+            filter_list.as_ref().map_or_else(HashSet::new, |list| {
+                list.iter()
+                    .flat_map(|line| tokenizer.tokenize(line).0)
+                    .collect()
+            });
+
+        let checked: Vec<(CorpusRow, Option<AdmissionRule>)> = entries
+            .into_par_iter()
+            .map(|row| {
+                let segments = tokenizer.tokenize(&row.search_term).0;
+                let rule = self.check(&row.search_term, &row.label, &segments, &filter_words);
+                (row, rule)
+            })
+            .collect();
+
+        let mut rejected: HashMap<AdmissionRule, usize> = HashMap::new();
+        let admitted = checked
+            .into_iter()
+            .filter_map(|(entry, rule)| match rule {
+                None => Some(entry),
+                Some(rule) => {
+                    *rejected.entry(rule).or_insert(0) += 1;
+                    None
+                }
+            })
+            .collect();
+
+        for (rule, count) in rejected {
+            println!("Rejected {count} entries by admission rule '{rule}'");
+        }
+        admitted
+    }
+}
+
+/// Options to prune generated variants (skip-grams, abbreviations, ...) after loading.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PruningOptions {
+    /// If true, generated variants are dropped if their key is also the key of a `Full` entry with
+    /// a different label. Defaults to false.
+    pub full_collisions: Option<bool>,
+    /// If true, generated variants are dropped if their key consists only of filter list words.
+    /// Defaults to false.
+    pub filter_words: Option<bool>,
+    /// If given, generated variants are dropped if their key is shared by more than this number of
+    /// distinct labels.
+    pub max_labels: Option<usize>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_admission_rules() {
+        let rules = RobustAdmissionRules::try_from(AdmissionRules {
+            min_chars: Some(2),
+            min_tokens: None,
+            reject_numeric: Some(true),
+            reject_filter_words: Some(true),
+            include_terms: None,
+            exclude_terms: Some(vec![String::from("^Art$")]),
+            include_labels: Some(vec![String::from("^https://www.gbif.org/")]),
+            exclude_labels: None,
+        })
+        .unwrap();
+        let entries: Vec<CorpusRow> = vec![
+            ("A", "https://www.gbif.org/species/1"),
+            ("1", "https://www.gbif.org/species/2"),
+            ("12 3", "https://www.gbif.org/species/3"),
+            ("Der Die", "https://www.gbif.org/species/4"),
+            ("Art", "https://www.gbif.org/species/5"),
+            ("Sula", "https://example.org/6"),
+            ("Sula bassana", "https://www.gbif.org/species/7"),
+        ]
+        .into_iter()
+        .map(|(term, label)| CorpusRow::from((String::from(term), String::from(label))))
+        .collect();
+        let filter_list = Some(vec![String::from("der"), String::from("die")]);
+
+        let admitted = rules.apply(entries, &Tokenizer::default(), &filter_list);
+        assert_eq!(
+            admitted,
+            vec![CorpusRow::from((
+                String::from("Sula bassana"),
+                String::from("https://www.gbif.org/species/7")
+            ))]
+        );
+    }
+}
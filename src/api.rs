@@ -1,16 +1,24 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::Read;
 use std::sync::Arc;
 
+use futures_util::stream::{self, StreamExt};
+use rayon::prelude::*;
+use tokio::sync::mpsc;
+
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use actix_files::NamedFile;
-use actix_web::web;
+use actix_web::web::{self, Bytes, BytesMut};
 use actix_web::HttpResponse;
 use actix_web::Result;
 
-use crate::tree::ResultSelection;
+use crate::filter::SearchFilter;
+use crate::sentence::SentenceBoundaries;
+use crate::tree::{HashMapSearchTree, Match, ResultSelection, SearchResult};
 use crate::util::parse_optional;
 use crate::AppState;
 
@@ -19,48 +27,706 @@ pub struct ProcessRequest<'r> {
     pub text: Cow<'r, str>,
     pub max_len: Option<String>,
     pub result_selection: Option<ResultSelection>,
+    pub resolve_abbreviations: Option<bool>,
+    /// If true, the labels of synonym matches are replaced by their accepted labels.
+    pub accepted_only: Option<bool>,
+    /// If true, the ancestors of the (accepted) label of each match are included.
+    pub include_ancestors: Option<bool>,
+    /// Character offsets of the tokens of a pre-tokenized text, which are used instead of the
+    /// tokenizer of the tree.
+    pub tokens: Option<Vec<(usize, usize)>>,
+    /// Character offsets of the sentences of the text, which are used instead of the sentence
+    /// segmenter of the tree.
+    pub sentences: Option<Vec<(usize, usize)>>,
+    /// If true, matches may cross sentence boundaries.
+    pub cross_sentences: Option<bool>,
+    #[serde(flatten)]
+    pub filter: SearchFilter,
 }
 
 pub async fn v1_communication_layer() -> Result<NamedFile> {
     Ok(NamedFile::open_async("communication_layer.lua").await?)
 }
 
+/// A span of the v2 API with all of its matches.
+#[derive(Debug, Serialize)]
+pub struct Span {
+    /// The text of the span as it occurs in the original text.
+    pub surface: String,
+    /// The normalized tokens of the span, joined by spaces.
+    pub string: String,
+    /// The begin and end character offsets in the original text.
+    pub begin: usize,
+    pub end: usize,
+    /// The index of the first token of the span and the index after its last token.
+    pub token_begin: usize,
+    pub token_end: usize,
+    pub matches: Vec<SpanMatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpanMatch {
+    #[serde(flatten)]
+    pub mtch: Match,
+    /// The ancestors of the (accepted) label, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ancestors: Option<Vec<Arc<String>>>,
+}
+
+/// Checks that the tokens and sentences of the request are non-empty, sorted, non-overlapping and
+/// within the bounds of the text.
+pub fn validate_spans(request: &ProcessRequest<'_>) -> Result<(), String> {
+    let length = request.text.chars().count();
+    for (kind, spans) in [("token", &request.tokens), ("sentence", &request.sentences)] {
+        let mut last_end = 0;
+        for &(begin, end) in spans.iter().flatten() {
+            if begin >= end || end > length || begin < last_end {
+                return Err(format!("Invalid {kind} span ({begin}, {end})"));
+            }
+            last_end = end;
+        }
+    }
+    Ok(())
+}
+
+/// Searches the text of the request and applies its post-processing options.
+pub fn process(tree: &HashMapSearchTree, request: &ProcessRequest<'_>) -> Vec<SearchResult> {
+    let max_len = parse_optional::<usize>(&request.max_len);
+    let result_selection = Option::from(&request.result_selection);
+    let boundaries = match (&request.sentences, request.cross_sentences) {
+        (_, Some(true)) => SentenceBoundaries::Ignore,
+        (Some(sentences), _) => SentenceBoundaries::Given(sentences.clone()),
+        (None, Some(false)) => SentenceBoundaries::Detect,
+        (None, None) => tree.default_boundaries(),
+    };
+    let mut results = tree.search_with_boundaries(
+        &request.text,
+        request.tokens.as_deref(),
+        &boundaries,
+        max_len,
+        result_selection,
+        Some(&request.filter),
+    );
+    if request.resolve_abbreviations.unwrap_or(false) {
+        results = tree.resolve_abbreviations(results);
+    }
+    if request.accepted_only.unwrap_or(false) {
+        results = tree.accepted_only(results);
+    }
+    results
+}
+
+/// Converts search results into v2 spans with their surface text and token indices.
+pub fn to_spans(
+    tree: &HashMapSearchTree,
+    request: &ProcessRequest<'_>,
+    results: Vec<SearchResult>,
+) -> Vec<Span> {
+    let text = &request.text;
+    let offsets = match &request.tokens {
+        Some(tokens) => tokens.clone(),
+        None => tree.tokenize(text).1,
+    };
+    let char_starts: Vec<usize> = text
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    let include_ancestors = request.include_ancestors.unwrap_or(false);
+
+    results
+        .into_iter()
+        .map(|(string, mtches, begin, end)| {
+            let token_begin = offsets.partition_point(|offset| offset.1 <= begin);
+            let token_end = offsets.partition_point(|offset| offset.0 < end);
+            let matches = mtches
+                .into_iter()
+                .map(|mtch| SpanMatch {
+                    ancestors: include_ancestors.then(|| tree.ancestors(&mtch)),
+                    mtch,
+                })
+                .collect();
+            Span {
+                surface: String::from(&text[char_starts[begin]..char_starts[end]]),
+                string,
+                begin,
+                end,
+                token_begin,
+                token_end: token_end.max(token_begin),
+                matches,
+            }
+        })
+        .collect()
+}
+
+pub async fn v2_process(
+    request: web::Json<ProcessRequest<'_>>,
+    state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    if let Err(err) = validate_spans(&request) {
+        return HttpResponse::BadRequest().body(err);
+    }
+    let tree = &state.get_ref().tree;
+    let results = process(tree, &request);
+    HttpResponse::Ok().json(json!({ "spans": to_spans(tree, &request, results) }))
+}
+
 pub async fn v1_process(
     request: web::Json<ProcessRequest<'_>>,
     state: web::Data<Arc<AppState>>,
 ) -> HttpResponse {
-    let results = state.get_ref().tree.search(
-        &request.text,
-        parse_optional::<usize>(&request.max_len),
-        Option::from(&request.result_selection),
-    );
-    let results: Vec<Value> = results
+    if let Err(err) = validate_spans(&request) {
+        return HttpResponse::BadRequest().body(err);
+    }
+    let tree = &state.get_ref().tree;
+    let results = process(tree, &request);
+    HttpResponse::Ok().json(to_v1_values(tree, &request, results))
+}
+
+/// Converts search results into the v1 format, which joins the strings, types and labels of all
+/// matches of a span.
+pub fn to_v1_values(
+    tree: &HashMapSearchTree,
+    request: &ProcessRequest<'_>,
+    results: Vec<SearchResult>,
+) -> Vec<Value> {
+    results
         .into_iter()
         .map(|(string, mtches, begin, end)| {
-            let mut value: HashMap<(String, String), Vec<String>> = HashMap::new();
+            let mut value: HashMap<(String, String), (Vec<String>, Vec<String>)> = HashMap::new();
+            let mut ancestors: HashMap<String, Vec<String>> = HashMap::new();
             for mtch in mtches {
-                value
+                if request.include_ancestors.unwrap_or(false) {
+                    let chain = tree.ancestors(&mtch);
+                    if !chain.is_empty() {
+                        ancestors.insert(
+                            mtch.match_label.to_string(),
+                            chain.iter().map(|label| label.to_string()).collect(),
+                        );
+                    }
+                }
+                let (labels, accepted_labels) = value
                     .entry((mtch.match_string.to_string(), mtch.match_type.to_string()))
-                    .and_modify(|e| e.push(mtch.match_label.to_string()))
-                    .or_insert_with(|| vec![mtch.match_label.to_string()]);
+                    .or_default();
+                labels.push(mtch.match_label.to_string());
+                if let Some(accepted_label) = mtch.accepted_label {
+                    accepted_labels.push(accepted_label.to_string());
+                }
             }
 
-            let ((match_strings, match_types), match_labels): (
-                (Vec<String>, Vec<String>),
-                Vec<String>,
-            ) = value
-                .into_iter()
-                .map(|((s, t), l)| ((s, t), l.join(" ")))
-                .unzip();
-            json!({
+            let (mut match_strings, mut match_types) = (Vec::new(), Vec::new());
+            let (mut match_labels, mut accepted_labels) = (Vec::new(), Vec::new());
+            for ((s, t), (l, a)) in value {
+                match_strings.push(s);
+                match_types.push(t);
+                match_labels.push(l.join(" "));
+                accepted_labels.push(a.join(" "));
+            }
+            let mut result = json!({
                 "string": string,
                 "match_labels": match_labels.join(" | "),
+                "accepted_labels": accepted_labels.join(" | "),
                 "match_types": match_types.join(" | "),
                 "match_strings": match_strings.join(" | "),
                 "begin": begin,
                 "end": end,
-            })
+            });
+            if request.include_ancestors.unwrap_or(false) {
+                result["ancestors"] = json!(ancestors);
+            }
+            result
         })
-        .collect::<Vec<Value>>();
-    HttpResponse::Ok().json(results)
+        .collect::<Vec<Value>>()
+}
+
+/// A document of a batch request: its id and the same options as a single request.
+#[derive(Debug, Deserialize)]
+pub struct BatchDocument {
+    pub id: String,
+    #[serde(flatten)]
+    pub request: ProcessRequest<'static>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub documents: Vec<Value>,
+}
+
+/// Returns the id of a document of a batch or stream, or the given index if it has none.
+fn document_id(idx: usize, document: &Value) -> String {
+    document
+        .get("id")
+        .and_then(Value::as_str)
+        .map_or_else(|| idx.to_string(), String::from)
+}
+
+/// Processes a single document of a batch or stream. Returns its id, or the given index if it has
+/// none, and its v1 results or an error.
+pub fn process_document(
+    tree: &HashMapSearchTree,
+    idx: usize,
+    document: Value,
+) -> (String, Result<Vec<Value>, String>) {
+    let fallback_id = document_id(idx, &document);
+    let document: BatchDocument = match serde_json::from_value(document) {
+        Ok(document) => document,
+        Err(err) => return (fallback_id, Err(format!("Invalid document: {err}"))),
+    };
+    let request = &document.request;
+    if request.max_len.is_some() && parse_optional::<usize>(&request.max_len).is_none() {
+        return (document.id, Err(String::from("Invalid max_len")));
+    }
+    if let Err(err) = validate_spans(request) {
+        return (document.id, Err(err));
+    }
+    let values = to_v1_values(tree, request, process(tree, request));
+    (document.id, Ok(values))
+}
+
+/// The v1 results and the errors of the documents of a batch, keyed by their ids.
+pub type BatchResults = (HashMap<String, Vec<Value>>, HashMap<String, String>);
+
+/// Processes all documents of a batch in parallel. Returns the v1 results and the errors of the
+/// documents keyed by their ids, or by their index if they have none. Batches with duplicate ids
+/// are rejected as a whole.
+pub fn process_batch(
+    tree: &HashMapSearchTree,
+    documents: Vec<Value>,
+) -> Result<BatchResults, String> {
+    let mut ids = HashSet::new();
+    for (idx, document) in documents.iter().enumerate() {
+        let id = document_id(idx, document);
+        if ids.contains(&id) {
+            return Err(format!("Duplicate document id '{id}'"));
+        }
+        ids.insert(id);
+    }
+
+    let outcomes: Vec<(String, Result<Vec<Value>, String>)> = documents
+        .into_par_iter()
+        .enumerate()
+        .map(|(idx, document)| process_document(tree, idx, document))
+        .collect();
+
+    let mut results = HashMap::new();
+    let mut errors = HashMap::new();
+    for (id, outcome) in outcomes {
+        match outcome {
+            Ok(values) => {
+                results.insert(id, values);
+            }
+            Err(err) => {
+                errors.insert(id, err);
+            }
+        }
+    }
+    Ok((results, errors))
+}
+
+pub async fn v1_process_batch(
+    request: web::Json<BatchRequest>,
+    state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse> {
+    let state = Arc::clone(state.get_ref());
+    let documents = request.into_inner().documents;
+    match web::block(move || process_batch(&state.tree, documents)).await? {
+        Ok((results, errors)) => {
+            Ok(HttpResponse::Ok().json(json!({ "results": results, "errors": errors })))
+        }
+        Err(err) => Ok(HttpResponse::BadRequest().body(err)),
+    }
+}
+
+/// The limits of the streaming endpoint.
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    /// The maximum length of a single line of the NDJSON request in bytes.
+    pub line_limit: usize,
+}
+
+struct StreamState {
+    payload: web::Payload,
+    buffer: BytesMut,
+    state: Arc<AppState>,
+    line_limit: usize,
+    idx: usize,
+    done: bool,
+}
+
+impl StreamState {
+    /// Returns the next non-empty line of the payload, reading further chunks only when needed.
+    async fn next_line(&mut self) -> Option<Result<Bytes, String>> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                if pos + 1 > self.line_limit {
+                    return Some(Err(format!(
+                        "Line exceeds the limit of {} bytes",
+                        self.line_limit
+                    )));
+                }
+                let line = self.buffer.split_to(pos + 1).freeze();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                return Some(Ok(line));
+            }
+            if self.buffer.len() > self.line_limit {
+                return Some(Err(format!(
+                    "Line exceeds the limit of {} bytes",
+                    self.line_limit
+                )));
+            }
+            match self.payload.next().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(err)) => return Some(Err(format!("Failed to read request: {err}"))),
+                None if self.buffer.iter().all(u8::is_ascii_whitespace) => return None,
+                None => return Some(Ok(self.buffer.split().freeze())),
+            }
+        }
+    }
+}
+
+fn ndjson_line(value: Value) -> Bytes {
+    let mut line = value.to_string();
+    line.push('\n');
+    Bytes::from(line)
+}
+
+/// Tags a newline-delimited JSON stream of documents and streams the results back as NDJSON, one
+/// line per document in the order of the request. The request is read lazily, so only one
+/// document is held in memory at a time and slow clients slow down the reading of the request.
+pub async fn v1_process_stream(
+    payload: web::Payload,
+    state: web::Data<Arc<AppState>>,
+    config: web::Data<StreamConfig>,
+) -> HttpResponse {
+    let stream_state = StreamState {
+        payload,
+        buffer: BytesMut::new(),
+        state: Arc::clone(state.get_ref()),
+        line_limit: config.line_limit,
+        idx: 0,
+        done: false,
+    };
+    let stream = stream::unfold(stream_state, |mut stream_state| async move {
+        if stream_state.done {
+            return None;
+        }
+        let line = match stream_state.next_line().await? {
+            Ok(line) => line,
+            Err(err) => {
+                stream_state.done = true;
+                let line = ndjson_line(json!({ "error": err }));
+                return Some((Ok::<Bytes, actix_web::Error>(line), stream_state));
+            }
+        };
+        let idx = stream_state.idx;
+        stream_state.idx += 1;
+
+        let line = match serde_json::from_slice::<Value>(&line) {
+            Err(err) => ndjson_line(
+                json!({ "id": idx.to_string(), "error": format!("Invalid document: {err}") }),
+            ),
+            Ok(document) => {
+                let state = Arc::clone(&stream_state.state);
+                match web::block(move || process_document(&state.tree, idx, document)).await {
+                    Ok((id, Ok(results))) => ndjson_line(json!({ "id": id, "results": results })),
+                    Ok((id, Err(err))) => ndjson_line(json!({ "id": id, "error": err })),
+                    Err(err) => {
+                        ndjson_line(json!({ "id": idx.to_string(), "error": err.to_string() }))
+                    }
+                }
+            }
+        };
+        Some((Ok(line), stream_state))
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
+}
+
+/// The number of chunks and result lines that are buffered by the raw text endpoint.
+const RAW_CHANNEL_CAPACITY: usize = 16;
+
+/// The options of a raw text request, given as query parameters.
+#[derive(Debug, Deserialize)]
+pub struct RawQuery {
+    pub max_len: Option<usize>,
+    pub language: Option<String>,
+}
+
+/// A blocking reader over the chunks of a request body that are forwarded by an async task.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Result<Bytes, String>>,
+    current: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(err)) => return Err(io::Error::other(err)),
+                None => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.current.len());
+        buf[..read].copy_from_slice(&self.current.split_to(read));
+        Ok(read)
+    }
+}
+
+/// Tags a raw UTF-8 text of arbitrary length, which is searched in chunks with
+/// [`HashMapSearchTree::search_reader`]. The results are streamed back as NDJSON, one line per
+/// span, while the text is still being uploaded.
+pub async fn v1_process_raw(
+    mut payload: web::Payload,
+    query: web::Query<RawQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let (chunk_sender, chunk_receiver) = mpsc::channel(RAW_CHANNEL_CAPACITY);
+    let (line_sender, line_receiver) = mpsc::channel::<Bytes>(RAW_CHANNEL_CAPACITY);
+
+    actix_web::rt::spawn(async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|err| format!("Failed to read request: {err}"));
+            let failed = chunk.is_err();
+            if chunk_sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let state = Arc::clone(state.get_ref());
+    let query = query.into_inner();
+    actix_web::rt::task::spawn_blocking(move || {
+        let filter = SearchFilter {
+            language: query.language,
+            ..Default::default()
+        };
+        let reader = ChannelReader {
+            receiver: chunk_receiver,
+            current: Bytes::new(),
+        };
+        for result in state
+            .tree
+            .search_reader(reader, query.max_len, None, Some(&filter))
+        {
+            let line = match result {
+                Ok((string, mtches, begin, end)) => ndjson_line(json!({
+                    "string": string,
+                    "begin": begin,
+                    "end": end,
+                    "matches": mtches,
+                })),
+                Err(err) => ndjson_line(json!({ "error": err.to_string() })),
+            };
+            if line_sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let stream = stream::unfold(line_receiver, |mut receiver| async move {
+        let line = receiver.recv().await?;
+        Some((Ok::<Bytes, actix_web::Error>(line), receiver))
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    pub term: String,
+}
+
+/// Returns all matches of a search term, including derived variants, without a document.
+pub async fn v1_lookup(
+    query: web::Query<LookupQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let (segments, mtches) = state.get_ref().tree.lookup_term(&query.term);
+    HttpResponse::Ok().json(json!({
+        "term": query.term,
+        "string": segments.join(" "),
+        "matches": mtches,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LabelQuery {
+    pub id: String,
+}
+
+/// A search term of the reverse label index with its match.
+#[derive(Debug, Serialize)]
+pub struct LabelEntry<'a> {
+    pub search_term: String,
+    #[serde(flatten)]
+    pub mtch: &'a Match,
+}
+
+/// Returns all search terms and variant types that point to a label.
+pub async fn v1_label(
+    query: web::Query<LabelQuery>,
+    state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let entries: Vec<LabelEntry> = state
+        .get_ref()
+        .tree
+        .lookup_label(&query.id)
+        .into_iter()
+        .map(|(search_term, mtch)| LabelEntry { search_term, mtch })
+        .collect();
+    HttpResponse::Ok().json(json!({ "id": query.id, "entries": entries }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_spans() {
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![(
+                "Sula bassana".to_string(),
+                "gbif:2481069 (synonym)".to_string(),
+            )],
+            false,
+            0,
+            0,
+            false,
+        );
+        let request: ProcessRequest =
+            serde_json::from_str(r#"{"text": "Über SULA  bassana."}"#).unwrap();
+        let results = process(&tree, &request);
+        let spans = to_spans(&tree, &request, results);
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].surface, "SULA  bassana");
+        assert_eq!(spans[0].string, "sula bassana");
+        assert_eq!((spans[0].begin, spans[0].end), (5, 18));
+        assert_eq!((spans[0].token_begin, spans[0].token_end), (1, 3));
+        assert_eq!(
+            spans[0].matches[0].mtch.match_label.as_str(),
+            "gbif:2481069 (synonym)"
+        );
+        assert!(spans[0].matches[0].ancestors.is_none());
+
+        let request: ProcessRequest = serde_json::from_str(
+            r#"{"text": "Über SULA  bassana.", "tokens": [[0, 4], [5, 9], [11, 18], [18, 19]]}"#,
+        )
+        .unwrap();
+        assert!(validate_spans(&request).is_ok());
+        let results = process(&tree, &request);
+        let spans = to_spans(&tree, &request, results);
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].begin, spans[0].end), (5, 18));
+        assert_eq!((spans[0].token_begin, spans[0].token_end), (1, 3));
+
+        let request: ProcessRequest =
+            serde_json::from_str(r#"{"text": "Über SULA  bassana.", "tokens": [[5, 9], [0, 4]]}"#)
+                .unwrap();
+        assert!(validate_spans(&request).is_err());
+    }
+
+    #[test]
+    fn test_process_batch() {
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![("Sula bassana".to_string(), "gbif:2481069".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        let documents: Vec<Value> = serde_json::from_str(
+            r#"[
+                {"id": "a", "text": "Sula bassana"},
+                {"id": "b", "text": "Sula bassana", "match_types": ["SkipGram"]},
+                {"id": "c"},
+                {"id": "d", "text": "Sula", "max_len": "zwei"},
+                {"text": "Sula bassana"}
+            ]"#,
+        )
+        .unwrap();
+        let (results, errors) = process_batch(&tree, documents).unwrap();
+
+        assert_eq!(results["a"].len(), 1);
+        assert_eq!(results["b"].len(), 0);
+        assert_eq!(results.len(), 2);
+        assert_eq!(errors.len(), 3);
+        assert!(errors["c"].starts_with("Invalid document"));
+        assert_eq!(errors["d"], "Invalid max_len");
+        assert!(errors["4"].starts_with("Invalid document"));
+
+        let documents: Vec<Value> = serde_json::from_str(
+            r#"[
+                {"id": "a", "text": "Sula bassana"},
+                {"id": "a", "text": "Sula"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            process_batch(&tree, documents),
+            Err(String::from("Duplicate document id 'a'"))
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_process_stream() {
+        use actix_web::{test, App};
+
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![("Sula bassana".to_string(), "gbif:2481069".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(AppState { tree })))
+                .app_data(web::Data::new(StreamConfig { line_limit: 64 }))
+                .route("/", web::post().to(v1_process_stream)),
+        )
+        .await;
+
+        let payload = "{\"id\": \"a\", \"text\": \"Sula bassana\"}\n\n{\"text\": 1}\n";
+        let request = test::TestRequest::post().uri("/").set_payload(payload);
+        let body = test::call_and_read_body(&app, request.to_request()).await;
+        let lines: Vec<Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["id"], "a");
+        assert_eq!(lines[0]["results"][0]["string"], "sula bassana");
+        assert_eq!(lines[1]["id"], "1");
+        assert!(lines[1]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid document"));
+
+        let payload = format!("{{\"id\": \"a\", \"text\": \"{}\"}}", "x".repeat(100));
+        let request = test::TestRequest::post().uri("/").set_payload(payload);
+        let body = test::call_and_read_body(&app, request.to_request()).await;
+        let line: Value = serde_json::from_slice(&body).unwrap();
+        assert!(line["error"].as_str().unwrap().starts_with("Line exceeds"));
+
+        // An oversized line that is terminated within the same chunk
+        let payload = format!(
+            "{{\"id\": \"a\", \"text\": \"{}\"}}\n{{\"id\": \"b\", \"text\": \"Sula\"}}\n",
+            "x".repeat(100)
+        );
+        let request = test::TestRequest::post().uri("/").set_payload(payload);
+        let body = test::call_and_read_body(&app, request.to_request()).await;
+        let line: Value =
+            serde_json::from_slice(body.split(|b| *b == b'\n').next().unwrap()).unwrap();
+        assert!(line["error"].as_str().unwrap().starts_with("Line exceeds"));
+    }
 }
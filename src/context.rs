@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::Tokenizer;
+
+const DEFAULT_CONTEXT_WINDOW: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextDirection {
+    Left,
+    Right,
+    #[default]
+    Both,
+}
+
+/// A rule that constrains the matches of a corpus by the tokens surrounding them, i.e. to require
+/// `Gattung` or `Art` before a genus or to reject names after `Herr` or `Frau`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContextRule {
+    /// If given, at least one of these tokens must occur within the context window.
+    pub required: Option<Vec<String>>,
+    /// None of these tokens may occur within the context window.
+    pub forbidden: Option<Vec<String>>,
+    /// The number of tokens to the left and/or right of a match. Defaults to 3.
+    pub window: Option<usize>,
+    /// The side(s) of the match the window covers. Defaults to both.
+    pub direction: Option<ContextDirection>,
+}
+
+#[derive(Debug, Default)]
+pub struct RobustContextRule {
+    pub required: Option<HashSet<String>>,
+    pub forbidden: HashSet<String>,
+    pub window: usize,
+    pub direction: ContextDirection,
+}
+
+impl RobustContextRule {
+    /// Normalizes the tokens of the rule with the given tokenizer, so they can be compared to the
+    /// tokens of a text.
+    pub fn new(rule: &ContextRule, tokenizer: &Tokenizer) -> Self {
+        let normalize = |tokens: &Vec<String>| -> HashSet<String> {
+            tokens
+                .iter()
+                .flat_map(|token| tokenizer.tokenize(token).0)
+                .collect()
+        };
+        RobustContextRule {
+            required: rule.required.as_ref().map(normalize),
+            forbidden: rule.forbidden.as_ref().map(normalize).unwrap_or_default(),
+            window: rule.window.unwrap_or(DEFAULT_CONTEXT_WINDOW),
+            direction: rule.direction.unwrap_or_default(),
+        }
+    }
+
+    /// Checks the rule against the tokens left and right of a match, both ordered as in the text.
+    pub fn check(&self, left: &[String], right: &[String]) -> bool {
+        let left = &left[left.len().saturating_sub(self.window)..];
+        let right = &right[..right.len().min(self.window)];
+        let context: Vec<&String> = match self.direction {
+            ContextDirection::Left => left.iter().collect(),
+            ContextDirection::Right => right.iter().collect(),
+            ContextDirection::Both => left.iter().chain(right).collect(),
+        };
+        if let Some(required) = &self.required {
+            if !context.iter().any(|token| required.contains(*token)) {
+                return false;
+            }
+        }
+        !context.iter().any(|token| self.forbidden.contains(*token))
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+/// The minimum number of characters of a compound part.
+pub const MIN_PART_LENGTH: usize = 3;
+
+/// Linking elements that may occur between two parts of a German compound, i.e. the `s` in
+/// `Tölpelskolonie` or the `en` in `Schwanenhals`.
+const LINKING_ELEMENTS: [&str; 5] = ["", "s", "es", "n", "en"];
+
+/// Splits compound tokens into parts that are contained in a vocabulary.
+#[derive(Debug, Default)]
+pub struct Decompounder {
+    vocabulary: HashSet<String>,
+}
+
+impl Decompounder {
+    pub fn new<'a>(tokens: impl Iterator<Item = &'a String>) -> Self {
+        Decompounder {
+            vocabulary: tokens
+                .filter(|token| token.chars().count() >= MIN_PART_LENGTH)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns the character ranges of the known parts of the given token.
+    ///
+    /// Parts are matched greedily with the longest known prefix, optionally followed by a linking
+    /// element and further known parts. If the chain does not reach the end of the token, the
+    /// longest known suffix (the head of the compound) is added. Tokens that are known as a whole
+    /// are not split.
+    pub fn split(&self, token: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = token.chars().collect();
+        if chars.len() < 2 * MIN_PART_LENGTH || self.vocabulary.contains(token) {
+            return Vec::new();
+        }
+
+        let mut parts = Vec::new();
+        let mut position = 0;
+        if let Some(end) = self.longest_part(&chars, position) {
+            parts.push((position, end));
+            position = end;
+            while position < chars.len() {
+                let next = LINKING_ELEMENTS.iter().find_map(|linking| {
+                    let start = position + linking.chars().count();
+                    let matches_linking = chars[position..]
+                        .iter()
+                        .take(linking.chars().count())
+                        .copied()
+                        .eq(linking.chars());
+                    if matches_linking {
+                        self.longest_part(&chars, start).map(|end| (start, end))
+                    } else {
+                        None
+                    }
+                });
+                match next {
+                    Some((start, end)) => {
+                        parts.push((start, end));
+                        position = end;
+                    }
+                    None => break,
+                }
+            }
+        }
+        if position < chars.len() {
+            let suffix = (position.max(1)..=chars.len() - MIN_PART_LENGTH).find(|start| {
+                self.vocabulary
+                    .contains(&chars[*start..].iter().collect::<String>())
+            });
+            if let Some(start) = suffix {
+                parts.push((start, chars.len()));
+            }
+        }
+        parts
+    }
+
+    fn longest_part(&self, chars: &[char], start: usize) -> Option<usize> {
+        (start + MIN_PART_LENGTH..=chars.len()).rev().find(|end| {
+            self.vocabulary
+                .contains(&chars[start..*end].iter().collect::<String>())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let vocabulary: Vec<String> = ["tölpel", "basstölpel", "sturmtaucher", "schwan", "hals"]
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        let decompounder = Decompounder::new(vocabulary.iter());
+
+        assert_eq!(decompounder.split("tölpelkolonie"), vec![(0, 6)]);
+        assert_eq!(decompounder.split("sturmtaucherbrutplatz"), vec![(0, 12)]);
+        assert_eq!(decompounder.split("schwanenhals"), vec![(0, 6), (8, 12)]);
+        assert_eq!(decompounder.split("kolonietölpel"), vec![(7, 13)]);
+        assert_eq!(decompounder.split("basstölpelkolonie"), vec![(0, 10)]);
+        assert_eq!(
+            decompounder.split("basstölpel"),
+            Vec::<(usize, usize)>::new()
+        );
+        assert_eq!(
+            decompounder.split("brutplatz"),
+            Vec::<(usize, usize)>::new()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hierarchy::LabelHierarchy;
+use crate::tree::{Match, MatchType};
+use crate::util::Tokenizer;
+
+/// Query-time filters that are applied during the search, before the result selection.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFilter {
+    /// Surface forms that should be ignored, i.e. `["die", "art"]`.
+    pub stopwords: Option<Vec<String>>,
+    /// If given, only matches with a label starting with any of these prefixes are returned.
+    pub include_labels: Option<Vec<String>>,
+    /// Matches with a label starting with any of these prefixes are not returned.
+    pub exclude_labels: Option<Vec<String>>,
+    /// If given, only matches of these types are returned.
+    pub match_types: Option<Vec<MatchType>>,
+    /// If given, only matches with any of these labels or with any of them among the ancestors of
+    /// their (accepted) label are returned, i.e. `["https://www.gbif.org/species/212"]` for birds.
+    pub ancestor_labels: Option<Vec<String>>,
+    /// The language of the text, selecting the filter list that is applied, i.e. `"de"`.
+    pub language: Option<String>,
+}
+
+impl SearchFilter {
+    /// Normalizes the stopwords with the given tokenizer, so they can be compared to the search
+    /// terms of a traversal. If given, the language filter list is applied in addition.
+    pub(crate) fn compile<'a>(
+        &'a self,
+        tokenizer: &Tokenizer,
+        language_list: Option<&'a HashSet<Vec<String>>>,
+        hierarchy: &'a LabelHierarchy,
+    ) -> CompiledFilter<'a> {
+        let stopwords = self
+            .stopwords
+            .as_ref()
+            .map_or_else(HashSet::new, |stopwords| {
+                stopwords
+                    .iter()
+                    .map(|stopword| tokenizer.tokenize(stopword).0)
+                    .filter(|segments| !segments.is_empty())
+                    .collect::<HashSet<Vec<String>>>()
+            });
+        CompiledFilter {
+            stopwords,
+            language_list,
+            hierarchy,
+            filter: self,
+        }
+    }
+}
+
+pub(crate) struct CompiledFilter<'a> {
+    stopwords: HashSet<Vec<String>>,
+    language_list: Option<&'a HashSet<Vec<String>>>,
+    hierarchy: &'a LabelHierarchy,
+    filter: &'a SearchFilter,
+}
+
+impl CompiledFilter<'_> {
+    pub(crate) fn is_stopword(&self, search_terms: &[String]) -> bool {
+        self.stopwords.contains(search_terms)
+            || self
+                .language_list
+                .is_some_and(|list| list.contains(search_terms))
+    }
+
+    pub(crate) fn accepts(&self, mtch: &Match) -> bool {
+        if let Some(match_types) = &self.filter.match_types {
+            if !match_types.contains(&mtch.match_type) {
+                return false;
+            }
+        }
+        if let Some(include_labels) = &self.filter.include_labels {
+            if !include_labels
+                .iter()
+                .any(|prefix| mtch.match_label.starts_with(prefix.as_str()))
+            {
+                return false;
+            }
+        }
+        if let Some(exclude_labels) = &self.filter.exclude_labels {
+            if exclude_labels
+                .iter()
+                .any(|prefix| mtch.match_label.starts_with(prefix.as_str()))
+            {
+                return false;
+            }
+        }
+        if let Some(ancestor_labels) = &self.filter.ancestor_labels {
+            if !self
+                .hierarchy
+                .is_descendant_of(&mtch.match_label, ancestor_labels)
+                && !mtch.accepted_label.as_ref().is_some_and(|accepted_label| {
+                    self.hierarchy
+                        .is_descendant_of(accepted_label, ancestor_labels)
+                })
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Filter lists keyed by language, which are selected per request.
+#[derive(Debug, Default)]
+pub struct FilterLists {
+    lists: HashMap<String, HashSet<Vec<String>>>,
+    /// If true, the language of a text is identified from its tokens if none is given.
+    pub detect_language: bool,
+}
+
+impl FilterLists {
+    pub fn insert(&mut self, language: &str, lines: &[String], tokenizer: &Tokenizer) {
+        self.lists
+            .entry(String::from(language))
+            .or_default()
+            .extend(
+                lines
+                    .iter()
+                    .map(|line| tokenizer.tokenize(line).0)
+                    .filter(|segments| !segments.is_empty()),
+            );
+    }
+
+    pub fn get(&self, language: &str) -> Option<&HashSet<Vec<String>>> {
+        self.lists.get(language)
+    }
+
+    /// Returns the single-token entries of all filter lists.
+    pub fn words(&self) -> impl Iterator<Item = &String> {
+        self.lists
+            .values()
+            .flatten()
+            .filter(|segments| segments.len() == 1)
+            .map(|segments| &segments[0])
+    }
+
+    /// Identifies the language of the given tokens as the language whose filter list contains
+    /// the most of them. Returns `None` if no token is contained in any filter list.
+    pub fn identify(&self, tokens: &[String]) -> Option<&str> {
+        self.lists
+            .iter()
+            .map(|(language, list)| {
+                let hits = tokens
+                    .iter()
+                    .filter(|token| list.contains(std::slice::from_ref(*token)))
+                    .count();
+                (language, hits)
+            })
+            .filter(|(_, hits)| *hits > 0)
+            .max_by(|(a_lang, a_hits), (b_lang, b_hits)| {
+                a_hits.cmp(b_hits).then_with(|| b_lang.cmp(a_lang))
+            })
+            .map(|(language, _)| language.as_str())
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// A hierarchy of labels, i.e. the family, order and class of a species.
+#[derive(Debug, Default)]
+pub struct LabelHierarchy {
+    parents: HashMap<String, Arc<String>>,
+}
+
+impl LabelHierarchy {
+    /// Sets the parent of the given label, replacing any previous parent.
+    pub fn insert(&mut self, label: &str, parent_label: Arc<String>) {
+        if label != parent_label.as_str() {
+            self.parents.insert(String::from(label), parent_label);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// Returns the ancestors of the given label, starting with its parent. Stops at cycles.
+    pub fn ancestors(&self, label: &str) -> Vec<Arc<String>> {
+        let mut visited: HashSet<&str> = HashSet::from([label]);
+        let mut ancestors = Vec::new();
+        let mut current = label;
+        while let Some(parent) = self.parents.get(current) {
+            if !visited.insert(parent.as_str()) {
+                break;
+            }
+            ancestors.push(parent.clone());
+            current = parent.as_str();
+        }
+        ancestors
+    }
+
+    /// Returns true if the label is one of the given labels or has one of them as an ancestor.
+    pub fn is_descendant_of(&self, label: &str, ancestor_labels: &[String]) -> bool {
+        ancestor_labels.iter().any(|ancestor| ancestor == label)
+            || self
+                .ancestors(label)
+                .iter()
+                .any(|ancestor| ancestor_labels.contains(ancestor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ancestors() {
+        let mut hierarchy = LabelHierarchy::default();
+        hierarchy.insert("gbif:sula_bassana", Arc::new(String::from("gbif:sula")));
+        hierarchy.insert("gbif:sula", Arc::new(String::from("gbif:sulidae")));
+        hierarchy.insert("gbif:sulidae", Arc::new(String::from("gbif:aves")));
+        hierarchy.insert("gbif:aves", Arc::new(String::from("gbif:sula")));
+
+        let ancestors: Vec<String> = hierarchy
+            .ancestors("gbif:sula_bassana")
+            .iter()
+            .map(|ancestor| ancestor.to_string())
+            .collect();
+        assert_eq!(ancestors, vec!["gbif:sula", "gbif:sulidae", "gbif:aves"]);
+        assert!(hierarchy.is_descendant_of("gbif:sula_bassana", &[String::from("gbif:aves")]));
+        assert!(hierarchy.is_descendant_of("gbif:aves", &[String::from("gbif:aves")]));
+        assert!(!hierarchy.is_descendant_of("gbif:aves", &[String::from("gbif:mammalia")]));
+    }
+}
@@ -1,6 +1,15 @@
+pub mod admission;
 pub mod api;
+pub mod context;
+pub mod decompound;
+pub mod filter;
+pub mod hierarchy;
+pub mod pattern;
+pub mod reader;
+pub mod sentence;
 pub mod tree;
 pub mod util;
+pub mod variants;
 
 use crate::tree::HashMapSearchTree;
 
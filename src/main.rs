@@ -4,14 +4,19 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
 use anyhow::Context;
-use clap::{arg, Parser};
+use clap::Parser;
 
 use actix_files as fs;
 use actix_web::{web, App, HttpServer};
 
+use gazetteer::admission::{AdmissionRules, PruningOptions};
 use gazetteer::api;
+use gazetteer::context::ContextRule;
 use gazetteer::tree::HashMapSearchTree;
-use gazetteer::util::{read_lines, CorpusFormat};
+use gazetteer::util::{read_hierarchy, read_lines, read_spelling_rules, CorpusFormat};
+use gazetteer::variants::{
+    AbbreviationGenerator, SkipGramGenerator, VariantGenerator, VariantGeneratorRegistry,
+};
 use gazetteer::AppState;
 
 #[cfg(feature = "gui")]
@@ -21,6 +26,7 @@ const DEFAULT_GENERATE_ABBRV: bool = false;
 const DEFAULT_GENERATE_SKIP_GRAMS: bool = false;
 const DEFAULT_SKIP_GRAM_MAX_SKIPS: i32 = 2;
 const DEFAULT_SKIP_GRAM_MIN_LENGTH: i32 = 2;
+const DEFAULT_DETECT_LANGUAGE: bool = false;
 
 #[cfg(debug_assertions)]
 const LOG_LEVEL: &str = "debug";
@@ -30,22 +36,52 @@ const LOG_LEVEL: &str = "info";
 #[derive(Serialize, Deserialize)]
 struct Config {
     filter_path: Option<String>,
+    filter_lists: Option<HashMap<String, String>>,
+    detect_language: Option<bool>,
+    decompound: Option<bool>,
+    character_tokenization: Option<bool>,
+    cross_sentences: Option<bool>,
+    spelling_rules_path: Option<String>,
     generate_abbrv: Option<bool>,
     generate_skip_grams: Option<bool>,
     skip_gram_min_length: Option<i32>,
     skip_gram_max_skips: Option<i32>,
+    admission: Option<AdmissionRules>,
+    pruning: Option<PruningOptions>,
+    generators: Option<Vec<toml::value::Table>>,
     corpora: HashMap<String, Corpus>,
 }
 
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum CorpusType {
+    /// A list of search terms and labels, the default.
+    #[default]
+    List,
+    /// A list of regular expressions and labels.
+    Regex,
+    /// A list of phrases that suppress all matches they overlap.
+    Blocker,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Corpus {
     path: String,
+    #[serde(rename = "type", default)]
+    corpus_type: CorpusType,
     filter_path: Option<String>,
     generate_abbrv: Option<bool>,
     generate_skip_grams: Option<bool>,
     skip_gram_min_length: Option<i32>,
     skip_gram_max_skips: Option<i32>,
     format: Option<CorpusFormat>,
+    admission: Option<AdmissionRules>,
+    generators: Option<Vec<toml::value::Table>>,
+    hierarchy_path: Option<String>,
+    context: Option<Vec<ContextRule>>,
+    /// Only valid at the top level, see [`Config`]. Rejected here, so that it is not silently
+    /// applied to all corpora.
+    character_tokenization: Option<bool>,
 }
 
 fn parse_args_and_build_tree(config_path: &str) -> anyhow::Result<HashMapSearchTree> {
@@ -54,30 +90,60 @@ fn parse_args_and_build_tree(config_path: &str) -> anyhow::Result<HashMapSearchT
 
     let config: Config = toml::from_str(&config).context("Failed to parse configuration TOML")?;
 
+    // Texts are tokenized once for all corpora, so the character mode can only be set globally
+    if let Some((name, _)) = config
+        .corpora
+        .iter()
+        .find(|(_, corpus)| corpus.character_tokenization.is_some())
+    {
+        anyhow::bail!(
+            "Invalid option 'character_tokenization' for corpus '{name}', it can only be set at the top level"
+        );
+    }
+
     let mut tree = HashMapSearchTree::default();
-    let default_filter_list = load_filter_list(config.filter_path);
+    tree.set_character_mode(config.character_tokenization.unwrap_or(false));
+    if let Some(spelling_rules_path) = &config.spelling_rules_path {
+        tree.set_spelling_rules(
+            read_spelling_rules(spelling_rules_path).context("Failed to load spelling rules")?,
+        );
+    }
+    let default_filter_list = load_filter_list(config.filter_path.clone());
 
-    for corpus in config.corpora.values() {
+    if let Some(filter_lists) = &config.filter_lists {
+        for (language, filter_path) in filter_lists {
+            tree.add_filter_list(language, &read_lines(filter_path));
+        }
+    }
+    tree.set_detect_language(config.detect_language.unwrap_or(DEFAULT_DETECT_LANGUAGE));
+
+    let registry = VariantGeneratorRegistry::default();
+    for (name, corpus) in &config.corpora {
         let path: &String = &corpus.path;
-        let generate_abbrv = corpus
-            .generate_abbrv
-            .unwrap_or_else(|| config.generate_abbrv.unwrap_or(DEFAULT_GENERATE_ABBRV));
-        let generate_skip_grams = corpus.generate_skip_grams.unwrap_or_else(|| {
-            config
-                .generate_skip_grams
-                .unwrap_or(DEFAULT_GENERATE_SKIP_GRAMS)
-        });
-        let skip_gram_min_length = corpus.skip_gram_min_length.unwrap_or_else(|| {
-            config
-                .skip_gram_min_length
-                .unwrap_or(DEFAULT_SKIP_GRAM_MIN_LENGTH)
-        });
-        let skip_gram_max_skips = corpus.skip_gram_max_skips.unwrap_or_else(|| {
-            config
-                .skip_gram_max_skips
-                .unwrap_or(DEFAULT_SKIP_GRAM_MAX_SKIPS)
-        });
+        match corpus.corpus_type {
+            CorpusType::List => {}
+            CorpusType::Regex => {
+                tree.load_regex_file(path, &corpus.format);
+                continue;
+            }
+            CorpusType::Blocker => {
+                tree.load_blocker_file(path);
+                continue;
+            }
+        }
+        let generators = match corpus.generators.as_ref().or(config.generators.as_ref()) {
+            Some(generators) => registry
+                .build_all(generators)
+                .context(format!("Invalid generators for corpus '{name}'"))?,
+            None => legacy_generators(&config, corpus),
+        };
+        tree.set_corpus(Some(name));
         let format = &corpus.format;
+        let admission = if corpus.admission.is_some() {
+            &corpus.admission
+        } else {
+            &config.admission
+        };
         if let Some(filter_path) = &corpus.filter_path {
             let lines: Vec<String> = read_lines(filter_path);
             let filter_list = if lines.is_empty() {
@@ -85,31 +151,66 @@ fn parse_args_and_build_tree(config_path: &str) -> anyhow::Result<HashMapSearchT
             } else {
                 Option::from(lines)
             };
-            tree.load_file(
-                path,
-                generate_skip_grams,
-                skip_gram_min_length,
-                skip_gram_max_skips,
-                &filter_list,
-                generate_abbrv,
-                format,
-            );
+            tree.load_file(path, &filter_list, format, admission, &generators);
         } else {
-            tree.load_file(
-                path,
-                generate_skip_grams,
-                skip_gram_min_length,
-                skip_gram_max_skips,
-                &default_filter_list,
-                generate_abbrv,
-                format,
+            tree.load_file(path, &default_filter_list, format, admission, &generators);
+        }
+        if let Some(hierarchy_path) = &corpus.hierarchy_path {
+            tree.add_hierarchy(
+                read_hierarchy(hierarchy_path, format)
+                    .context(format!("Failed to load hierarchy for corpus '{name}'"))?,
             );
         }
+        if let Some(context) = &corpus.context {
+            tree.add_context_rules(name, context);
+        }
     }
+    tree.set_corpus(None);
+    if let Some(pruning) = &config.pruning {
+        tree.prune(pruning, &default_filter_list);
+    }
+    tree.set_decompound(config.decompound.unwrap_or(false));
+    tree.set_cross_sentences(config.cross_sentences.unwrap_or(false));
+    tree.build_label_index();
     println!("Finished loading gazetteer.");
     Ok(tree)
 }
 
+/// Builds the generators from the `generate_skip_grams` and `generate_abbrv` options, which are used
+/// if no `generators` are configured.
+fn legacy_generators(config: &Config, corpus: &Corpus) -> Vec<Box<dyn VariantGenerator>> {
+    let generate_abbrv = corpus
+        .generate_abbrv
+        .unwrap_or_else(|| config.generate_abbrv.unwrap_or(DEFAULT_GENERATE_ABBRV));
+    let generate_skip_grams = corpus.generate_skip_grams.unwrap_or_else(|| {
+        config
+            .generate_skip_grams
+            .unwrap_or(DEFAULT_GENERATE_SKIP_GRAMS)
+    });
+    let skip_gram_min_length = corpus.skip_gram_min_length.unwrap_or_else(|| {
+        config
+            .skip_gram_min_length
+            .unwrap_or(DEFAULT_SKIP_GRAM_MIN_LENGTH)
+    });
+    let skip_gram_max_skips = corpus.skip_gram_max_skips.unwrap_or_else(|| {
+        config
+            .skip_gram_max_skips
+            .unwrap_or(DEFAULT_SKIP_GRAM_MAX_SKIPS)
+    });
+
+    let mut generators: Vec<Box<dyn VariantGenerator>> = Vec::new();
+    if generate_skip_grams {
+        generators.push(Box::new(SkipGramGenerator {
+            min_length: skip_gram_min_length,
+            max_skips: skip_gram_max_skips,
+        }));
+    }
+    if generate_abbrv {
+        generators.push(Box::new(AbbreviationGenerator {}));
+    }
+    generators
+}
+
 fn load_filter_list(filter_path: Option<String>) -> Option<Vec<String>> {
     let lines = filter_path.map_or_else(Vec::new, |p| read_lines(&p));
     if lines.is_empty() {
@@ -130,7 +231,11 @@ struct Args {
     port: u16,
     #[arg(short, long, default_value_t = 1)]
     workers: usize,
-    #[arg(long, default_value_t = 16_777_216, help = "The request size limit")]
+    #[arg(
+        long,
+        default_value_t = 16_777_216,
+        help = "The request size limit, and the line size limit of streaming requests"
+    )]
     limit: usize,
 }
 
@@ -150,6 +255,9 @@ async fn main() -> anyhow::Result<()> {
         tree: parse_args_and_build_tree(&args.config)?,
     });
     let data: web::Data<Arc<AppState>> = web::Data::new(state);
+    let stream_config = web::Data::new(api::StreamConfig {
+        line_limit: args.limit,
+    });
 
     HttpServer::new(move || {
         let app = App::new()
@@ -157,6 +265,7 @@ async fn main() -> anyhow::Result<()> {
             .wrap(actix_web::middleware::Logger::default())
             .wrap(actix_web::middleware::Compress::default())
             .app_data(json_config.clone())
+            .app_data(stream_config.clone())
             .service(
                 web::resource("/v1/process")
                     .wrap(
@@ -165,6 +274,28 @@ async fn main() -> anyhow::Result<()> {
                     )
                     .route(web::post().to(api::v1_process)),
             )
+            .service(
+                web::resource("/v1/process_batch")
+                    .wrap(
+                        actix_web::middleware::DefaultHeaders::default()
+                            .add(("Content-Type", "application/json")),
+                    )
+                    .route(web::post().to(api::v1_process_batch)),
+            )
+            .service(web::resource("/v1/process_raw").route(web::post().to(api::v1_process_raw)))
+            .service(
+                web::resource("/v1/process_stream").route(web::post().to(api::v1_process_stream)),
+            )
+            .service(
+                web::resource("/v2/process")
+                    .wrap(
+                        actix_web::middleware::DefaultHeaders::default()
+                            .add(("Content-Type", "application/json")),
+                    )
+                    .route(web::post().to(api::v2_process)),
+            )
+            .service(web::resource("/v1/lookup").route(web::get().to(api::v1_lookup)))
+            .service(web::resource("/v1/label").route(web::get().to(api::v1_label)))
             .service(
                 web::resource("/v1/communication_layer")
                     .route(web::get().to(api::v1_communication_layer)),
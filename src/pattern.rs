@@ -0,0 +1,124 @@
+use anyhow::{anyhow, bail};
+
+use crate::util::Tokenizer;
+
+/// The token that represents a single-token wildcard in the keys of the search tree. It can not be
+/// produced by the tokenizer, so it only matches through the wildcard lookup.
+pub const WILDCARD: &str = "\u{0}*";
+
+/// The maximum number of token sequences a single pattern may expand to.
+pub const MAX_EXPANSIONS: usize = 1024;
+
+/// An element of a pattern: a list of alternative token sequences, which may be empty if the
+/// element is optional.
+type Element = Vec<Vec<String>>;
+
+/// Expands a pattern into all token sequences it matches, where wildcards are represented by
+/// [`WILDCARD`] tokens.
+///
+/// The pattern syntax supports:
+/// - alternatives in brackets, i.e. `Puffinus [sp|spec|spp]`,
+/// - optional words or alternatives with a trailing `?`, i.e. `Sula bassana?`,
+/// - single-token wildcards `*` and optional single-token wildcards `?`, i.e. `Sula ? bassana`.
+pub fn expand_pattern(pattern: &str, tokenizer: &Tokenizer) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut expansions: Vec<Vec<String>> = vec![Vec::new()];
+    for element in parse_pattern(pattern, tokenizer)? {
+        if expansions.len() * element.len() > MAX_EXPANSIONS {
+            bail!("Pattern expands to more than {MAX_EXPANSIONS} token sequences");
+        }
+        expansions = expansions
+            .iter()
+            .flat_map(|prefix| {
+                element.iter().map(move |alternative| {
+                    let mut expansion = prefix.clone();
+                    expansion.extend(alternative.iter().cloned());
+                    expansion
+                })
+            })
+            .collect();
+    }
+    expansions.retain(|expansion| expansion.iter().any(|token| token != WILDCARD));
+    expansions.sort();
+    expansions.dedup();
+    if expansions.is_empty() {
+        bail!("Pattern does not contain any tokens");
+    }
+    Ok(expansions)
+}
+
+fn parse_pattern(pattern: &str, tokenizer: &Tokenizer) -> anyhow::Result<Vec<Element>> {
+    let mut elements = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut element: Element = match c {
+            c if c.is_whitespace() => continue,
+            '*' => vec![vec![String::from(WILDCARD)]],
+            '?' => vec![vec![String::from(WILDCARD)], Vec::new()],
+            ']' | '|' => return Err(anyhow!("Unexpected '{c}' in pattern '{pattern}'")),
+            '[' => {
+                let mut group = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('[') | None => bail!("Unclosed '[' in pattern '{pattern}'"),
+                        Some(c) => group.push(c),
+                    }
+                }
+                group
+                    .split('|')
+                    .map(|alternative| tokenizer.tokenize(alternative).0)
+                    .collect()
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '[' | ']' | '|' | '?' | '*') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                vec![tokenizer.tokenize(&word).0]
+            }
+        };
+        if chars.peek() == Some(&'?') && !element.iter().any(Vec::is_empty) {
+            chars.next();
+            element.push(Vec::new());
+        }
+        elements.push(element);
+    }
+    Ok(elements)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expand(pattern: &str) -> Vec<String> {
+        expand_pattern(pattern, &Tokenizer::default())
+            .unwrap()
+            .iter()
+            .map(|expansion| expansion.join(" ").replace(WILDCARD, "*"))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_pattern() {
+        assert_eq!(
+            expand("Puffinus [sp|spec.|spp]"),
+            vec!["puffinus sp", "puffinus spec", "puffinus spp"]
+        );
+        assert_eq!(
+            expand("Sula ? bassana"),
+            vec!["sula * bassana", "sula bassana"]
+        );
+        assert_eq!(expand("Sula bassana?"), vec!["sula", "sula bassana"]);
+        assert_eq!(
+            expand("[Großer|Kleiner] Sturmtaucher"),
+            vec!["großer sturmtaucher", "kleiner sturmtaucher"]
+        );
+        assert_eq!(expand("Sula *"), vec!["sula *"]);
+        assert!(expand_pattern("Sula [bassana", &Tokenizer::default()).is_err());
+        assert!(expand_pattern("* ?", &Tokenizer::default()).is_err());
+    }
+}
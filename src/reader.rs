@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+use crate::filter::SearchFilter;
+use crate::tree::{HashMapSearchTree, ResultSelection, SearchResult};
+
+/// The default number of bytes that are read from the reader per chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 1_048_576;
+
+/// The default number of bytes of buffered text after which the buffer is searched and drained
+/// regardless of pending matches.
+pub const DEFAULT_MAX_BUFFER: usize = 16 * DEFAULT_CHUNK_SIZE;
+
+/// An iterator over the search results of a text that is read in overlapping chunks, see
+/// [`HashMapSearchTree::search_reader`].
+pub struct SearchReader<'a, R: Read> {
+    tree: &'a HashMapSearchTree,
+    reader: R,
+    max_len: usize,
+    /// The number of tokens that are kept as look-ahead and carried as left context, at least
+    /// `max_len`, the largest context rule window and the blocker tree depth.
+    reach: usize,
+    result_selection: Option<&'a ResultSelection>,
+    filter: Option<&'a SearchFilter>,
+    chunk_size: usize,
+    max_buffer: usize,
+    /// The text that has been read but not searched completely yet.
+    buffer: String,
+    /// Trailing bytes of the last chunk that are not a complete UTF-8 character yet.
+    incomplete: Vec<u8>,
+    /// The character offset of the buffer in the whole text.
+    buffer_offset: usize,
+    /// Results starting before this character offset of the buffer have already been returned.
+    valid_from: usize,
+    /// The end offset of the last returned result, to resolve overlaps across chunks.
+    last_end: Option<usize>,
+    pending: VecDeque<SearchResult>,
+    eof: bool,
+}
+
+impl HashMapSearchTree {
+    /// Searches a text of arbitrary length with bounded memory. The text is read in chunks, each of
+    /// which is searched together with the last tokens of the previous chunk, so matches across
+    /// chunk boundaries are found. Results are only returned once enough text follows them to
+    /// apply regex entries, context rules and blockers, unless the buffered text exceeds a size
+    /// limit, i.e. for a single very long token. The results are returned incrementally in order with offsets
+    /// relative to the whole text.
+    pub fn search_reader<'a, R: Read>(
+        &'a self,
+        reader: R,
+        max_len: Option<usize>,
+        result_selection: Option<&'a ResultSelection>,
+        filter: Option<&'a SearchFilter>,
+    ) -> SearchReader<'a, R> {
+        let max_len = max_len.unwrap_or(self.tree_depth()).max(1);
+        SearchReader {
+            tree: self,
+            reader,
+            max_len,
+            reach: max_len.max(self.context_reach()),
+            result_selection,
+            filter,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_buffer: DEFAULT_MAX_BUFFER,
+            buffer: String::new(),
+            incomplete: Vec::new(),
+            buffer_offset: 0,
+            valid_from: 0,
+            last_end: None,
+            pending: VecDeque::new(),
+            eof: false,
+        }
+    }
+}
+
+impl<R: Read> SearchReader<'_, R> {
+    /// Sets the number of bytes that are read per chunk.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Sets the number of buffered bytes after which the buffer is searched and drained even if
+    /// matches at its end may be incomplete. At least twice the chunk size is buffered.
+    pub fn with_max_buffer(mut self, max_buffer: usize) -> Self {
+        self.max_buffer = max_buffer;
+        self
+    }
+
+    /// Reads the next chunk into the buffer. Returns false at the end of the text.
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        let mut chunk = std::mem::take(&mut self.incomplete);
+        let start = chunk.len();
+        chunk.resize(start + self.chunk_size, 0);
+        let read = loop {
+            match self.reader.read(&mut chunk[start..]) {
+                Ok(read) => break read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        };
+        chunk.truncate(start + read);
+        if read == 0 {
+            // Replace an incomplete character at the end of the text
+            self.buffer.push_str(&String::from_utf8_lossy(&chunk));
+            return Ok(false);
+        }
+        match std::str::from_utf8(&chunk) {
+            Ok(text) => self.buffer.push_str(text),
+            Err(err) if err.error_len().is_none() => {
+                let (valid, incomplete) = chunk.split_at(err.valid_up_to());
+                self.buffer.push_str(std::str::from_utf8(valid).unwrap());
+                self.incomplete = incomplete.to_vec();
+            }
+            Err(err) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Searches the buffer and moves the complete results to the pending results. Unless the
+    /// text is exhausted, the last `reach` tokens and the last, possibly partial token are only
+    /// look-ahead. Results ending in the look-ahead are deferred together with all later results,
+    /// and the text from `reach` tokens before the first deferred result is kept for the next
+    /// chunk. If the buffer exceeds the size limit, it is searched as if the text ended.
+    fn search_buffer(&mut self) {
+        let forced = !self.eof && self.buffer.len() > self.max_buffer.max(2 * self.chunk_size);
+        let (_, offsets) = self.tree.tokenize(&self.buffer);
+        let limit = if self.eof || forced {
+            None
+        } else if offsets.len() <= 2 * self.reach + 1 {
+            return;
+        } else {
+            Some(offsets[offsets.len() - self.reach - 1].0)
+        };
+
+        let results = self.tree.search_with_filter(
+            &self.buffer,
+            Some(self.max_len),
+            self.result_selection,
+            self.filter,
+        );
+        let mut resume = limit;
+        for (string, mtches, start, end) in results {
+            if start < self.valid_from {
+                continue;
+            }
+            if let Some(limit) = limit {
+                if end > limit {
+                    resume = Some(start.min(limit));
+                    break;
+                }
+            }
+            let (start, end) = (start + self.buffer_offset, end + self.buffer_offset);
+            if self.last_end == Some(end) {
+                continue;
+            }
+            self.last_end = Some(end);
+            self.pending.push_back((string, mtches, start, end));
+        }
+
+        match resume {
+            Some(resume) => {
+                let carry_idx = offsets
+                    .partition_point(|offset| offset.0 < resume)
+                    .saturating_sub(self.reach);
+                let carry_from = offsets[carry_idx].0.min(resume);
+                let carry_byte = self
+                    .buffer
+                    .char_indices()
+                    .nth(carry_from)
+                    .map_or(self.buffer.len(), |(idx, _)| idx);
+                self.buffer.drain(..carry_byte);
+                self.buffer_offset += carry_from;
+                self.valid_from = resume - carry_from;
+            }
+            None => {
+                self.buffer_offset += self.buffer.chars().count();
+                self.buffer.clear();
+                self.valid_from = 0;
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for SearchReader<'_, R> {
+    type Item = io::Result<SearchResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(Ok(result));
+            }
+            if self.eof {
+                return None;
+            }
+            match self.read_chunk() {
+                Ok(true) => {}
+                Ok(false) => self.eof = true,
+                Err(err) => {
+                    self.eof = true;
+                    return Some(Err(err));
+                }
+            }
+            // If the buffer holds too few tokens, the next chunk is appended before searching
+            self.search_buffer();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::{ContextDirection, ContextRule};
+
+    #[test]
+    fn test_search_reader() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Sula".to_string(), "gbif:sula".to_string()),
+            (
+                "Großer Sturmtaucher".to_string(),
+                "gbif:ardenna_gravis".to_string(),
+            ),
+        ];
+        tree.load(entries, false, 0, 0, true);
+        tree.set_corpus(Some("species"));
+        tree.load(
+            vec![("Sula bassana".to_string(), "gbif:sula_bassana".to_string())],
+            false,
+            0,
+            0,
+            true,
+        );
+        tree.set_corpus(None);
+        tree.add_context_rules(
+            "species",
+            &[ContextRule {
+                required: Some(vec!["brütet".to_string()]),
+                window: Some(3),
+                direction: Some(ContextDirection::Right),
+                ..Default::default()
+            }],
+        );
+        tree.add_regex(r"\d+ Paare auf der Insel", "count").unwrap();
+        tree.add_blockers(&["Sula zählte gestern noch alle".to_string()]);
+        let tree = tree;
+
+        let text =
+            "Über Sula bassana und den Großer Sturmtaucher. Sula zählte gestern noch alle 12 \
+                    Paare auf der Insel, wo Sula bassana am Felsen brütet. "
+                .repeat(20);
+        let expected = tree.search(&text, None, None);
+        assert_eq!(expected.len(), 60);
+        for chunk_size in [1, 7, 16, 64, 1000] {
+            let results: Vec<SearchResult> = tree
+                .search_reader(text.as_bytes(), None, None, None)
+                .with_chunk_size(chunk_size)
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(results, expected, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_search_reader_max_buffer() {
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![("Sula".to_string(), "gbif:sula".to_string())],
+            false,
+            0,
+            0,
+            true,
+        );
+        let tree = tree;
+
+        let text = format!("Sula {} Sula", "a".repeat(10_000));
+        let mut reader = tree
+            .search_reader(text.as_bytes(), None, None, None)
+            .with_chunk_size(16)
+            .with_max_buffer(256);
+        let mut results = Vec::new();
+        while let Some(result) = reader.next() {
+            assert!(reader.buffer.len() <= 256 + 16);
+            results.push(result.unwrap());
+        }
+        assert_eq!(
+            results.iter().map(|result| result.2).collect::<Vec<_>>(),
+            vec![0, 10_006]
+        );
+    }
+}
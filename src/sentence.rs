@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+/// Abbreviations that do not end a sentence, i.e. `Puffinus sp. Die Art …` has no boundary after
+/// `sp.`. Initials, ordinals and abbreviations with inner periods like `z.B.` are recognized
+/// without being listed here.
+pub const DEFAULT_ABBREVIATIONS: [&str; 30] = [
+    "sp", "spp", "ssp", "subsp", "var", "subvar", "f", "fam", "gen", "nov", "comb", "cf", "aff",
+    "agg", "syn", "nom", "emend", "auct", "al", "ca", "fig", "abb", "tab", "vgl", "bzw", "nr",
+    "dr", "prof", "st", "ex",
+];
+
+/// Characters that end a sentence if they are followed by whitespace and no lowercase letter.
+const TERMINATORS: [char; 4] = ['.', '!', '?', '…'];
+
+/// Characters that may follow a terminator and still belong to the sentence.
+const CLOSING: [char; 8] = ['"', '\'', ')', ']', '»', '«', '”', '’'];
+
+/// How sentence boundaries are determined for a search. Matches never cross the boundaries, unless
+/// they are ignored.
+#[derive(Debug, Clone, Default)]
+pub enum SentenceBoundaries {
+    /// Sentences are detected with the [`SentenceSegmenter`] of the tree.
+    #[default]
+    Detect,
+    /// The character offsets of the sentences, i.e. from an upstream pipeline.
+    Given(Vec<(usize, usize)>),
+    /// Matches may cross sentence boundaries.
+    Ignore,
+}
+
+/// A rule-based sentence segmenter that is aware of abbreviations.
+#[derive(Debug, Clone)]
+pub struct SentenceSegmenter {
+    abbreviations: HashSet<String>,
+}
+
+impl Default for SentenceSegmenter {
+    fn default() -> Self {
+        SentenceSegmenter::new(DEFAULT_ABBREVIATIONS.iter().copied())
+    }
+}
+
+impl SentenceSegmenter {
+    pub fn new<'a>(abbreviations: impl Iterator<Item = &'a str>) -> Self {
+        SentenceSegmenter {
+            abbreviations: abbreviations
+                .map(|abbreviation| abbreviation.to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Returns the character offsets of the sentences of the text, without surrounding
+    /// whitespace.
+    ///
+    /// A sentence ends at a terminator (and following closing quotes or brackets) if it is
+    /// followed by whitespace and the next word does not start with a lowercase letter, unless the
+    /// word before a period is an abbreviation. Empty lines always end a sentence.
+    pub fn segment(&self, text: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        let mut idx = 0;
+        while idx < chars.len() {
+            let end = if TERMINATORS.contains(&chars[idx]) {
+                let mut end = idx + 1;
+                while end < chars.len()
+                    && (TERMINATORS.contains(&chars[end]) || CLOSING.contains(&chars[end]))
+                {
+                    end += 1;
+                }
+                let next = chars[end..].iter().position(|c| !c.is_whitespace());
+                let ends_sentence = match next {
+                    Some(0) => false,
+                    Some(offset) => {
+                        !chars[end + offset].is_lowercase() && !self.is_abbreviation(&chars, idx)
+                    }
+                    None => true,
+                };
+                ends_sentence.then_some(end)
+            } else if chars[idx] == '\n' {
+                let blank_line = chars[idx + 1..]
+                    .iter()
+                    .take_while(|c| c.is_whitespace())
+                    .any(|c| *c == '\n');
+                blank_line.then_some(idx)
+            } else {
+                None
+            };
+            match end {
+                Some(end) => {
+                    push_sentence(&mut sentences, &chars, start, end);
+                    start = end;
+                    idx = end.max(idx + 1);
+                }
+                None => idx += 1,
+            }
+        }
+        push_sentence(&mut sentences, &chars, start, chars.len());
+        sentences
+    }
+
+    /// Returns true if the terminator at the given index is a period after an abbreviation, an
+    /// initial or an ordinal number.
+    fn is_abbreviation(&self, chars: &[char], idx: usize) -> bool {
+        if chars[idx] != '.' {
+            return false;
+        }
+        let word_start = chars[..idx]
+            .iter()
+            .rposition(|c| c.is_whitespace() || *c == '(' || *c == '[')
+            .map_or(0, |position| position + 1);
+        let word: String = chars[word_start..idx].iter().collect();
+        if word.is_empty() {
+            return false;
+        }
+        word.contains('.')
+            || (word.chars().count() == 1 && word.chars().all(char::is_alphabetic))
+            || word.chars().all(|c| c.is_ascii_digit())
+            || self.abbreviations.contains(&word.to_lowercase())
+    }
+}
+
+/// Adds the given range of characters as a sentence, trimmed of whitespace, unless it is empty.
+fn push_sentence(sentences: &mut Vec<(usize, usize)>, chars: &[char], start: usize, end: usize) {
+    let Some(first) = chars[start..end].iter().position(|c| !c.is_whitespace()) else {
+        return;
+    };
+    let last = chars[start..end]
+        .iter()
+        .rposition(|c| !c.is_whitespace())
+        .unwrap();
+    sentences.push((start + first, start + last + 1));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_segment() {
+        let segmenter = SentenceSegmenter::default();
+        let text =
+            "Wir sahen Sula. Bassana fehlte. Puffinus sp. Nov. und P. puffinus (am 12. Mai).\n\n\
+                    Überschrift\n\nNeuer Absatz!";
+        let sentences: Vec<String> = segmenter
+            .segment(text)
+            .into_iter()
+            .map(|(start, end)| text.chars().skip(start).take(end - start).collect())
+            .collect();
+        assert_eq!(
+            sentences,
+            vec![
+                "Wir sahen Sula.",
+                "Bassana fehlte.",
+                "Puffinus sp. Nov. und P. puffinus (am 12. Mai).",
+                "Überschrift",
+                "Neuer Absatz!",
+            ]
+        );
+        assert!(segmenter.segment(" \n ").is_empty());
+    }
+}
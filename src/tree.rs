@@ -1,22 +1,37 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
 use rayon::prelude::*;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::admission::{AdmissionRules, PruningOptions, RobustAdmissionRules};
+use crate::context::{ContextRule, RobustContextRule};
+use crate::decompound::Decompounder;
+use crate::filter::{CompiledFilter, FilterLists, SearchFilter};
+use crate::hierarchy::LabelHierarchy;
+use crate::pattern::{expand_pattern, WILDCARD};
+use crate::sentence::{SentenceBoundaries, SentenceSegmenter};
 use crate::util::{
-    create_skip_grams, get_files, parse_files, CorpusFormat, Tokenizer, TokensAndOffsets,
+    get_files, parse_files, read_lines, CorpusFormat, CorpusRow, SpellingRule, Tokenizer,
+    TokensAndOffsets,
 };
+use crate::variants::{AbbreviationGenerator, SkipGramGenerator, Variant, VariantGenerator};
 
 #[derive(Debug, Serialize, Deserialize)] // FIXME
 pub enum ResultSelection {
     All,
     Last,
     LastPreferFull,
+    /// Like `Last`, but returns only the given number of labels with the highest weights, each with
+    /// its highest weighted match.
+    TopK(usize),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -25,6 +40,20 @@ pub enum MatchType {
     Full,
     Abbreviated,
     SkipGram,
+    /// A scientific name without its author citation.
+    Canonical,
+    /// A scientific name without its rank marker, i.e. `Homo sapiens sapiens`.
+    Trinomial,
+    /// A scientific name reduced to its genus and species epithet.
+    Binomial,
+    /// A scientific name with abbreviated genus and species, i.e. `P. p. puffinus`.
+    TaxonAbbreviated,
+    /// An inflected form of an entry, i.e. `Basstölpels`.
+    Inflected,
+    /// A match of a regular expression on the original text, i.e. a year or coordinates.
+    Regex,
+    /// A match of a part of a compound token, i.e. `Tölpel` in `Tölpelkolonie`.
+    Compound,
 }
 
 impl MatchType {
@@ -34,6 +63,13 @@ impl MatchType {
             Self::Full => 0,
             Self::Abbreviated => 1,
             Self::SkipGram => 2,
+            Self::Canonical => 3,
+            Self::Trinomial => 4,
+            Self::Binomial => 5,
+            Self::TaxonAbbreviated => 6,
+            Self::Inflected => 7,
+            Self::Regex => 8,
+            Self::Compound => 9,
         }
     }
 }
@@ -65,15 +101,65 @@ impl Display for MatchType {
             Self::SkipGram => {
                 write!(f, "SkipGram")
             }
+            Self::Canonical => {
+                write!(f, "Canonical")
+            }
+            Self::Trinomial => {
+                write!(f, "Trinomial")
+            }
+            Self::Binomial => {
+                write!(f, "Binomial")
+            }
+            Self::TaxonAbbreviated => {
+                write!(f, "TaxonAbbreviated")
+            }
+            Self::Inflected => {
+                write!(f, "Inflected")
+            }
+            Self::Regex => {
+                write!(f, "Regex")
+            }
+            Self::Compound => {
+                write!(f, "Compound")
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Match {
     pub match_type: MatchType,
     pub match_string: Arc<String>,
     pub match_label: Arc<String>,
+    /// The accepted label, if the matched entry is a synonym.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accepted_label: Option<Arc<String>>,
+    /// The weight of the matched entry, if given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i64>,
+    /// The name of the corpus of the matched entry, if given. The corpus is not part of the
+    /// equality of matches, so the same entry in multiple corpora is only matched once, with the
+    /// corpus it was loaded from first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corpus: Option<Arc<String>>,
+}
+
+impl PartialEq for Match {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Match {}
+
+impl Hash for Match {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.match_type.hash(state);
+        self.match_string.hash(state);
+        self.match_label.hash(state);
+        self.accepted_label.hash(state);
+        self.weight.hash(state);
+    }
 }
 
 impl Ord for Match {
@@ -82,6 +168,8 @@ impl Ord for Match {
             .cmp(&other.match_type)
             .then(self.match_string.cmp(&other.match_string))
             .then(self.match_label.cmp(&other.match_label))
+            .then(self.accepted_label.cmp(&other.accepted_label))
+            .then(self.weight.cmp(&other.weight))
     }
 }
 
@@ -103,24 +191,46 @@ impl Display for Match {
 
 #[derive(Debug, Default)]
 pub struct HashMapSearchTree {
-    pub search_map: HashMap<Vec<String>, HashSet<Match>>,
+    pub search_map: HashMap<Arc<[String]>, HashSet<Match>>,
     tokenizer: Tokenizer,
     tree_depth: usize,
+    filter_lists: FilterLists,
+    accepted_labels: HashMap<Arc<String>, Arc<String>>,
+    hierarchy: LabelHierarchy,
+    /// The positions of the wildcards in all keys that contain any.
+    wildcard_masks: Vec<Vec<bool>>,
+    regex_entries: Vec<RegexEntry>,
+    /// A separate tree of phrases that suppress any overlapping match, i.e. `Herr Sula`.
+    blockers: Option<Box<HashMapSearchTree>>,
+    /// The corpus that is recorded on all inserted matches.
+    corpus: Option<Arc<String>>,
+    context_rules: HashMap<Arc<String>, Vec<RobustContextRule>>,
+    decompounder: Option<Decompounder>,
+    segmenter: SentenceSegmenter,
+    cross_sentences: bool,
+    /// The keys of the entries of each label and accepted label, shared with the search map, see
+    /// [`HashMapSearchTree::build_label_index`].
+    label_index: HashMap<Arc<String>, Vec<Arc<[String]>>>,
 }
 
-type EntryType = (Vec<String>, Arc<String>, Arc<String>);
+/// An entry of a regex corpus: the compiled pattern, the pattern string and the label.
+type RegexEntry = (Regex, Arc<String>, Arc<String>);
+
+/// The segments, search term, label and weight of an entry.
+type EntryType = (Vec<String>, Arc<String>, Arc<String>, Option<i64>);
+
+/// A single search result: the matched (normalized) search terms, the matches and the begin and end
+/// offsets of the span in the original text.
+pub type SearchResult = (String, Vec<Match>, usize, usize);
 
 impl HashMapSearchTree {
-    #[allow(clippy::too_many_arguments)]
     pub fn load_file(
         &mut self,
         root_path: &str,
-        generate_skip_grams: bool,
-        skip_gram_min_length: i32,
-        skip_gram_max_skips: i32,
         filter_list: &Option<Vec<String>>,
-        generate_abbrv: bool,
         format: &Option<CorpusFormat>,
+        admission_rules: &Option<AdmissionRules>,
+        generators: &[Box<dyn VariantGenerator>],
     ) {
         let files: Vec<String> = get_files(root_path);
         println!("Found {} files to read", files.len());
@@ -129,20 +239,95 @@ impl HashMapSearchTree {
         pb.set_style(
             ProgressStyle::with_template("Loading Input Files {bar:40} {pos}/{len} {msg}").unwrap(),
         );
-        let lines: Vec<(String, String)> =
-            parse_files(&files, Option::from(&pb), format, filter_list)
-                .expect("Failed to parse an input file");
+        let lines: Vec<CorpusRow> = parse_files(&files, Option::from(&pb), format, filter_list)
+            .expect("Failed to parse an input file");
         pb.finish_with_message("Done");
 
-        self.load(
-            lines,
-            generate_skip_grams,
-            skip_gram_min_length,
-            skip_gram_max_skips,
-            generate_abbrv,
+        let lines = match admission_rules {
+            None => lines,
+            Some(rules) => RobustAdmissionRules::try_from(rules.clone())
+                .expect("Failed to compile admission rules")
+                .apply(lines, &self.tokenizer, filter_list),
+        };
+
+        self.load_rows(lines, generators);
+    }
+
+    /// Loads a regex corpus, in which the search term column contains regular expressions that
+    /// are evaluated on the original text of each request.
+    pub fn load_regex_file(&mut self, root_path: &str, format: &Option<CorpusFormat>) {
+        let files: Vec<String> = get_files(root_path);
+        println!("Found {} regex files to read", files.len());
+
+        let rows: Vec<CorpusRow> =
+            parse_files(&files, None, format, &None).expect("Failed to parse an input file");
+        let mut skipped = 0;
+        for row in rows {
+            if let Err(err) = self.add_regex(&row.search_term, &row.label) {
+                println!("Skipped regex '{}': {err}", row.search_term);
+                skipped += 1;
+            }
+        }
+        println!(
+            "Loaded {} regex entries, skipped {skipped}",
+            self.regex_entries.len()
         );
     }
 
+    /// Loads a blocker corpus, in which each line contains a phrase that suppresses all matches it
+    /// overlaps. Any columns after the first are ignored.
+    pub fn load_blocker_file(&mut self, root_path: &str) {
+        let files: Vec<String> = get_files(root_path);
+        println!("Found {} blocker files to read", files.len());
+
+        let phrases: Vec<String> = files
+            .iter()
+            .flat_map(|file| read_lines(file))
+            .filter_map(|line| {
+                line.split('\t')
+                    .next()
+                    .map(|phrase| phrase.trim().to_string())
+            })
+            .filter(|phrase| !phrase.is_empty() && !phrase.starts_with('#'))
+            .collect();
+        self.add_blockers(&phrases);
+    }
+
+    /// Adds phrases that suppress all matches they overlap to the blocker tree.
+    pub fn add_blockers(&mut self, phrases: &[String]) {
+        let tokenizer = self.tokenizer.clone();
+        let blockers = self.blockers.get_or_insert_with(|| {
+            Box::new(HashMapSearchTree {
+                tokenizer,
+                ..Default::default()
+            })
+        });
+        let blocker_label = Arc::new(String::from("blocker"));
+        for phrase in phrases {
+            let segments = blockers.tokenize(phrase).0;
+            if !segments.is_empty() {
+                blockers.insert(
+                    segments,
+                    Arc::new(phrase.clone()),
+                    blocker_label.clone(),
+                    MatchType::Full,
+                );
+            }
+        }
+        println!("Loaded {} blocker phrases", blockers.search_map.len());
+    }
+
+    /// Adds a regular expression that is evaluated on the original text of each request.
+    pub fn add_regex(&mut self, pattern: &str, label: &str) -> anyhow::Result<()> {
+        let regex = Regex::new(pattern)?;
+        self.regex_entries.push((
+            regex,
+            Arc::new(String::from(pattern)),
+            Arc::new(String::from(label)),
+        ));
+        Ok(())
+    }
+
     pub fn load(
         &mut self,
         entries: Vec<(String, String)>,
@@ -151,25 +336,92 @@ impl HashMapSearchTree {
         skip_gram_max_skips: i32,
         generate_abbrv: bool,
     ) {
-        let search_terms: Vec<&str> = entries.iter().map(|line| line.0.as_str()).collect();
+        let mut generators: Vec<Box<dyn VariantGenerator>> = Vec::new();
+        if generate_skip_grams {
+            generators.push(Box::new(SkipGramGenerator {
+                min_length: skip_gram_min_length,
+                max_skips: skip_gram_max_skips,
+            }));
+        }
+        if generate_abbrv {
+            generators.push(Box::new(AbbreviationGenerator {}));
+        }
+        self.load_with_generators(entries, &generators);
+    }
+
+    /// Loads the given entries and expands them with each of the generators in order.
+    pub fn load_with_generators(
+        &mut self,
+        entries: Vec<(String, String)>,
+        generators: &[Box<dyn VariantGenerator>],
+    ) {
+        self.load_rows(
+            entries.into_iter().map(CorpusRow::from).collect(),
+            generators,
+        );
+    }
+
+    /// Like [`HashMapSearchTree::load_with_generators`], but also records the accepted and parent
+    /// labels of the rows. The weight of each row is stored on its matches, including the
+    /// generated variants.
+    ///
+    /// Pattern rows are expanded into all their token sequences, the generators are not applied
+    /// to them.
+    pub fn load_rows(&mut self, rows: Vec<CorpusRow>, generators: &[Box<dyn VariantGenerator>]) {
+        let (pattern_rows, rows): (Vec<CorpusRow>, Vec<CorpusRow>) =
+            rows.into_iter().partition(|row| row.pattern);
+
+        let search_terms: Vec<&str> = rows.iter().map(|row| row.search_term.as_str()).collect();
         let segmented: Vec<TokensAndOffsets> = self.tokenize_batch(search_terms.as_slice());
         let entries: Vec<EntryType> = segmented
             .into_iter()
-            .zip(entries)
-            .map(|(segments, (search_term, label))| {
-                (segments.0, Arc::from(search_term), Arc::from(label))
+            .zip(rows)
+            .map(|(segments, row)| {
+                let (search_term, label, weight) = self.record_row(row);
+                (segments.0, search_term, label, weight)
             })
             .collect();
 
+        let mut pattern_entries: Vec<EntryType> = Vec::new();
+        for row in pattern_rows {
+            match expand_pattern(&row.search_term, &self.tokenizer) {
+                Ok(expansions) => {
+                    let (search_term, label, weight) = self.record_row(row);
+                    pattern_entries.extend(expansions.into_iter().map(|segments| {
+                        (
+                            segments,
+                            Arc::clone(&search_term),
+                            Arc::clone(&label),
+                            weight,
+                        )
+                    }));
+                }
+                Err(err) => println!("Skipped pattern '{}': {err}", row.search_term),
+            }
+        }
+
         self.load_entries(&entries);
+        if !pattern_entries.is_empty() {
+            self.load_entries(&pattern_entries);
+        }
 
-        if generate_skip_grams {
-            self.generate_skip_grams(&entries, skip_gram_min_length, skip_gram_max_skips);
+        for generator in generators {
+            self.generate_variants(&entries, generator.as_ref());
         }
+    }
 
-        if generate_abbrv {
-            self.generate_abbreviations(&entries);
+    /// Records the accepted label, parent label and weight of the row and returns its search term
+    /// and label.
+    fn record_row(&mut self, row: CorpusRow) -> (Arc<String>, Arc<String>, Option<i64>) {
+        let label = Arc::from(row.label);
+        if let Some(accepted_label) = row.accepted_label {
+            self.accepted_labels
+                .insert(Arc::clone(&label), Arc::from(accepted_label));
+        }
+        if let Some(parent_label) = row.parent_label {
+            self.hierarchy.insert(&label, Arc::from(parent_label));
         }
+        (Arc::from(row.search_term), label, row.weight)
     }
 
     pub(crate) fn load_entries(&mut self, entries: &Vec<EntryType>) {
@@ -178,12 +430,13 @@ impl HashMapSearchTree {
             ProgressStyle::with_template("Loading Entries {bar:40} {pos}/{len} {msg}").unwrap(),
         );
 
-        for (segments, search_term, label) in entries {
-            self.insert(
+        for (segments, search_term, label, weight) in entries {
+            self.insert_weighted(
                 segments.clone(),
                 search_term.clone(),
                 label.clone(),
                 MatchType::Full,
+                *weight,
             );
             pb.inc(1);
         }
@@ -196,106 +449,331 @@ impl HashMapSearchTree {
         match_string: Arc<String>,
         match_label: Arc<String>,
         match_type: MatchType,
+    ) {
+        self.insert_weighted(segments, match_string, match_label, match_type, None);
+    }
+
+    /// Like [`HashMapSearchTree::insert`], but stores the given weight on the match.
+    pub fn insert_weighted(
+        &mut self,
+        segments: Vec<String>,
+        match_string: Arc<String>,
+        match_label: Arc<String>,
+        match_type: MatchType,
+        weight: Option<i64>,
     ) {
         if segments.len() > self.tree_depth {
             self.tree_depth = segments.len();
         }
+        if segments.iter().any(|segment| segment == WILDCARD) {
+            let mask: Vec<bool> = segments.iter().map(|segment| segment == WILDCARD).collect();
+            if !self.wildcard_masks.contains(&mask) {
+                self.wildcard_masks.push(mask);
+            }
+        }
 
-        match self.search_map.get_mut(&segments) {
+        let accepted_label = self.accepted_labels.get(&match_label).cloned();
+        let corpus = self.corpus.clone();
+        match self.search_map.get_mut(segments.as_slice()) {
             Some(search_result) => {
                 search_result.insert(Match {
                     match_type,
                     match_string,
                     match_label,
+                    accepted_label,
+                    weight,
+                    corpus,
                 });
             }
             None => {
                 self.search_map.insert(
-                    segments,
+                    Arc::from(segments),
                     HashSet::from([Match {
                         match_type,
                         match_string,
                         match_label,
+                        accepted_label,
+                        weight,
+                        corpus,
                     }]),
                 );
             }
         }
     }
 
-    pub(crate) fn generate_skip_grams(
+    pub(crate) fn generate_variants(
         &mut self,
-        lines: &[EntryType],
-        min_length: i32,
-        max_skips: i32,
+        entries: &[EntryType],
+        generator: &dyn VariantGenerator,
     ) {
-        let filtered = lines
-            .iter()
-            .filter(|(segments, _, _)| segments.len() > min_length as usize)
-            .collect::<Vec<_>>();
-
-        let pb = ProgressBar::new(filtered.len() as u64);
+        let pb = ProgressBar::new(entries.len() as u64);
         pb.set_style(
-            ProgressStyle::with_template("Generating skip-grams {bar:40} {pos}/{len} {msg}")
-                .unwrap(),
+            ProgressStyle::with_template(&format!(
+                "Generating {} {{bar:40}} {{pos}}/{{len}} {{msg}}",
+                generator.name()
+            ))
+            .unwrap(),
         );
 
+        let variants: Vec<Vec<Variant>> = entries
+            .par_iter()
+            .map(|(segments, search_term, _, _)| {
+                let variants = generator.generate(search_term, segments, &self.tokenizer);
+                pb.inc(1);
+                variants
+            })
+            .collect();
+
         let mut counter: i64 = 0;
-        for (segments, search_term, label) in filtered {
-            let mut deletes = create_skip_grams(vec![segments.clone()], max_skips, min_length);
-            deletes.sort();
-            deletes.dedup();
-            for skip_gram in deletes {
-                self.insert(
-                    skip_gram,
+        for ((_, search_term, label, weight), variants) in entries.iter().zip(variants) {
+            for (segments, match_type) in variants {
+                if segments.is_empty() {
+                    continue;
+                }
+                self.insert_weighted(
+                    segments,
                     search_term.clone(),
                     label.clone(),
-                    MatchType::SkipGram,
+                    match_type,
+                    *weight,
                 );
                 counter += 1;
             }
-            pb.inc(1);
         }
-        pb.finish_with_message(format!("Generated {counter} skip-grams"));
+        pb.finish_with_message(format!("Generated {counter} variants"));
     }
 
-    pub(crate) fn generate_abbreviations(&mut self, lines: &[EntryType]) {
-        let filtered = lines
-            .iter()
-            .filter(|(segments, _, _)| segments.len() > 1)
-            .collect::<Vec<_>>();
+    /// Removes generated variants that are likely to produce false positives, see
+    /// [`PruningOptions`]. Returns the number of pruned variants.
+    pub fn prune(&mut self, options: &PruningOptions, filter_list: &Option<Vec<String>>) -> usize {
+        let full_collisions = options.full_collisions.unwrap_or(false);
+        let max_labels = options.max_labels;
+        let filter_words: HashSet<String> = if options.filter_words.unwrap_or(false) {
+            filter_list
+                .iter()
+                .flatten()
+                .flat_map(|line| self.tokenize(line).0)
+                .chain(self.filter_lists.words().cloned())
+                .collect()
+        } else {
+            HashSet::new()
+        };
 
-        let pb = ProgressBar::new(filtered.len() as u64);
-        pb.set_style(
-            ProgressStyle::with_template("Generating Abbreviations {bar:40} {pos}/{len} {msg}")
-                .unwrap(),
-        );
+        let (mut collisions, mut filtered, mut ambiguous) = (0, 0, 0);
+        for (segments, mtches) in self.search_map.iter_mut() {
+            let full_labels: HashSet<Arc<String>> = mtches
+                .iter()
+                .filter(|mtch| mtch.match_type == MatchType::Full)
+                .map(|mtch| mtch.match_label.clone())
+                .collect();
+            if full_labels.len() == mtches.len() {
+                continue;
+            }
 
-        let mut counter: i64 = 0;
-        let mut abbrv: Vec<String> = Vec::new();
-        for (segments, search_term, label) in filtered {
-            for i in 0..(segments.len() - 1) {
-                abbrv.clear();
-                let target_segment = String::from(&segments[i]);
-                let abbreviated_segment = target_segment.chars().next().unwrap().to_string();
-
-                if i > 0 {
-                    abbrv.extend_from_slice(&segments[0..i]);
+            if !filter_words.is_empty() && segments.iter().all(|s| filter_words.contains(s)) {
+                let before = mtches.len();
+                mtches.retain(|mtch| mtch.match_type == MatchType::Full);
+                filtered += before - mtches.len();
+                continue;
+            }
+
+            if full_collisions && !full_labels.is_empty() {
+                let before = mtches.len();
+                mtches.retain(|mtch| {
+                    mtch.match_type == MatchType::Full || full_labels.contains(&mtch.match_label)
+                });
+                collisions += before - mtches.len();
+            }
+
+            if let Some(max_labels) = max_labels {
+                let derived_labels: HashSet<&Arc<String>> = mtches
+                    .iter()
+                    .filter(|mtch| mtch.match_type != MatchType::Full)
+                    .map(|mtch| &mtch.match_label)
+                    .collect();
+                if derived_labels.len() > max_labels {
+                    let before = mtches.len();
+                    mtches.retain(|mtch| mtch.match_type == MatchType::Full);
+                    ambiguous += before - mtches.len();
                 }
-                abbrv.push(abbreviated_segment);
-                abbrv.extend_from_slice(&segments[(i + 1)..]);
+            }
+        }
+        self.search_map.retain(|_, mtches| !mtches.is_empty());
 
-                self.insert(
-                    abbrv.clone(),
-                    search_term.clone(),
-                    label.clone(),
-                    MatchType::Abbreviated,
-                );
-                counter += 1;
+        println!("Pruned {collisions} generated variants colliding with full entries");
+        println!("Pruned {filtered} generated variants consisting of filter words");
+        println!("Pruned {ambiguous} generated variants shared by too many labels");
+        collisions + filtered + ambiguous
+    }
+
+    /// Adds label and parent label pairs to the label hierarchy.
+    pub fn add_hierarchy(&mut self, pairs: Vec<(String, String)>) {
+        for (label, parent_label) in pairs {
+            self.hierarchy.insert(&label, Arc::from(parent_label));
+        }
+    }
+
+    /// Returns the ancestors of the (accepted) label of the given match, starting with its parent.
+    pub fn ancestors(&self, mtch: &Match) -> Vec<Arc<String>> {
+        let label = mtch.accepted_label.as_ref().unwrap_or(&mtch.match_label);
+        self.hierarchy.ancestors(label)
+    }
+
+    /// Sets the name of the corpus that is recorded on all subsequently inserted matches.
+    pub fn set_corpus(&mut self, corpus: Option<&str>) {
+        self.corpus = corpus.map(|corpus| Arc::new(String::from(corpus)));
+    }
+
+    /// Adds context rules for the matches of the given corpus, which are applied to the search
+    /// results.
+    pub fn add_context_rules(&mut self, corpus: &str, rules: &[ContextRule]) {
+        let rules = rules
+            .iter()
+            .map(|rule| RobustContextRule::new(rule, &self.tokenizer));
+        self.context_rules
+            .entry(Arc::new(String::from(corpus)))
+            .or_default()
+            .extend(rules);
+    }
+
+    /// If enabled, tokens of a text are split into parts from the vocabulary of the tree, which are
+    /// returned as `Compound` matches. Must be called after all entries are loaded.
+    pub fn set_decompound(&mut self, decompound: bool) {
+        self.decompounder = if decompound {
+            let decompounder = Decompounder::new(
+                self.search_map
+                    .keys()
+                    .filter(|segments| segments.len() == 1)
+                    .map(|segments| &segments[0]),
+            );
+            println!("Built decompounding vocabulary");
+            Some(decompounder)
+        } else {
+            None
+        };
+    }
+
+    /// Sets the spelling rules of the tokenizer, which are applied to both entries and texts.
+    /// Must be called before any entries or filter lists are loaded.
+    pub fn set_spelling_rules(&mut self, spelling_rules: Vec<SpellingRule>) {
+        self.tokenizer.set_spelling_rules(spelling_rules);
+    }
+
+    /// Builds the reverse index from labels to their search terms. Must be called after all entries
+    /// are loaded and pruned.
+    pub fn build_label_index(&mut self) {
+        let mut label_index: HashMap<Arc<String>, Vec<Arc<[String]>>> = HashMap::new();
+        for (segments, mtches) in &self.search_map {
+            for mtch in mtches {
+                for label in std::iter::once(&mtch.match_label).chain(&mtch.accepted_label) {
+                    label_index
+                        .entry(Arc::clone(label))
+                        .or_default()
+                        .push(Arc::clone(segments));
+                }
             }
-            pb.inc(1);
         }
+        for keys in label_index.values_mut() {
+            keys.sort();
+            keys.dedup();
+            keys.shrink_to_fit();
+        }
+        println!("Built label index with {} labels", label_index.len());
+        self.label_index = label_index;
+    }
+
+    /// Returns the normalized segments of the given term and all of its matches, including derived
+    /// variants, without searching a text. The term is tokenized like a document.
+    pub fn lookup_term(&self, term: &str) -> (Vec<String>, Vec<Match>) {
+        let (segments, _) = self.tokenize(term);
+        let mtches = self
+            .lookup(&segments)
+            .into_iter()
+            .cloned()
+            .sorted()
+            .dedup()
+            .collect();
+        (segments, mtches)
+    }
+
+    /// Returns all search terms and their matches that point to the given label, either as their
+    /// label or as their accepted label. Wildcards of pattern entries are shown as `*`.
+    pub fn lookup_label(&self, label: &str) -> Vec<(String, &Match)> {
+        let Some(keys) = self.label_index.get(&String::from(label)) else {
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        for key in keys {
+            let search_term = key
+                .iter()
+                .map(|segment| if segment == WILDCARD { "*" } else { segment })
+                .join(" ");
+            let mut mtches: Vec<&Match> = self.search_map[key]
+                .iter()
+                .filter(|mtch| {
+                    mtch.match_label.as_str() == label
+                        || mtch.accepted_label.as_deref().map(String::as_str) == Some(label)
+                })
+                .collect();
+            mtches.sort();
+            entries.extend(mtches.into_iter().map(|mtch| (search_term.clone(), mtch)));
+        }
+        entries.sort();
+        entries
+    }
+
+    /// If enabled, matches may cross sentence boundaries unless a request says otherwise.
+    pub fn set_cross_sentences(&mut self, cross_sentences: bool) {
+        self.cross_sentences = cross_sentences;
+    }
+
+    /// The sentence boundaries of searches without explicit boundaries.
+    pub fn default_boundaries(&self) -> SentenceBoundaries {
+        if self.cross_sentences {
+            SentenceBoundaries::Ignore
+        } else {
+            SentenceBoundaries::Detect
+        }
+    }
+
+    /// Enables the character tokenization of CJK scripts for both entries and texts. Must be called
+    /// before any entries or filter lists are loaded.
+    pub fn set_character_mode(&mut self, character_mode: bool) {
+        self.tokenizer.set_character_mode(character_mode);
+    }
+
+    /// Adds a filter list for the given language, which is applied at query time if the
+    /// language of a request matches.
+    pub fn add_filter_list(&mut self, language: &str, lines: &[String]) {
+        self.filter_lists.insert(language, lines, &self.tokenizer);
+    }
+
+    /// If enabled, the language of texts without an explicit language is identified using the
+    /// language filter lists.
+    pub fn set_detect_language(&mut self, detect_language: bool) {
+        self.filter_lists.detect_language = detect_language;
+    }
+
+    /// The number of tokens of the longest key.
+    pub(crate) fn tree_depth(&self) -> usize {
+        self.tree_depth
+    }
 
-        pb.finish_with_message(format!("Generated {} abbreviated entries", counter));
+    /// The number of tokens around a match that decide whether it is returned, i.e. the largest
+    /// context rule window and the depth of the blocker tree.
+    pub(crate) fn context_reach(&self) -> usize {
+        let window = self
+            .context_rules
+            .values()
+            .flatten()
+            .map(|rule| rule.window)
+            .max()
+            .unwrap_or(0);
+        let blocker_depth = self
+            .blockers
+            .as_ref()
+            .map_or(0, |blockers| blockers.tree_depth);
+        window.max(blocker_depth)
     }
 
     pub(crate) fn tokenize(&self, input: &str) -> TokensAndOffsets {
@@ -311,11 +789,102 @@ impl HashMapSearchTree {
         text: &'a str,
         max_len: Option<usize>,
         result_selection: Option<&ResultSelection>,
-    ) -> Vec<(String, Vec<Match>, usize, usize)> {
+    ) -> Vec<SearchResult> {
+        self.search_with_filter(text, max_len, result_selection, None)
+    }
+
+    /// Like [`HashMapSearchTree::search`], but applies the given [`SearchFilter`] to all
+    /// traversal results before the result selection.
+    pub fn search_with_filter<'a>(
+        &'a self,
+        text: &'a str,
+        max_len: Option<usize>,
+        result_selection: Option<&ResultSelection>,
+        filter: Option<&SearchFilter>,
+    ) -> Vec<SearchResult> {
+        let boundaries = self.default_boundaries();
+        self.search_with_boundaries(text, None, &boundaries, max_len, result_selection, filter)
+    }
+
+    /// Like [`HashMapSearchTree::search_with_filter`], but uses the given tokens of a pre-tokenized
+    /// text, i.e. from an upstream pipeline, instead of the tokenizer. The tokens are only
+    /// normalized, see [`Tokenizer::normalize_tokens`], so all results are aligned to them.
+    pub fn search_pretokenized<'a>(
+        &'a self,
+        text: &'a str,
+        tokens: &[(usize, usize)],
+        max_len: Option<usize>,
+        result_selection: Option<&ResultSelection>,
+        filter: Option<&SearchFilter>,
+    ) -> Vec<SearchResult> {
+        let boundaries = self.default_boundaries();
+        self.search_with_boundaries(
+            text,
+            Some(tokens),
+            &boundaries,
+            max_len,
+            result_selection,
+            filter,
+        )
+    }
+
+    /// Searches the text, optionally with the given tokens of a pre-tokenized text, such that no
+    /// match crosses the given sentence boundaries.
+    pub fn search_with_boundaries<'a>(
+        &'a self,
+        text: &'a str,
+        tokens: Option<&[(usize, usize)]>,
+        boundaries: &SentenceBoundaries,
+        max_len: Option<usize>,
+        result_selection: Option<&ResultSelection>,
+        filter: Option<&SearchFilter>,
+    ) -> Vec<SearchResult> {
+        let tokens = match tokens {
+            Some(tokens) => self.tokenizer.normalize_tokens(text, tokens),
+            None => self.tokenize(text),
+        };
+        let sentences = match boundaries {
+            SentenceBoundaries::Detect => Some(Cow::Owned(self.segmenter.segment(text))),
+            SentenceBoundaries::Given(sentences) => Some(Cow::Borrowed(sentences.as_slice())),
+            SentenceBoundaries::Ignore => None,
+        };
+        self.search_tokens(
+            text,
+            tokens,
+            sentences.as_deref(),
+            max_len,
+            result_selection,
+            filter,
+        )
+    }
+
+    /// Searches the normalized tokens of the text with their character offsets.
+    fn search_tokens(
+        &self,
+        text: &str,
+        (mut slices, mut offsets): TokensAndOffsets,
+        sentences: Option<&[(usize, usize)]>,
+        max_len: Option<usize>,
+        result_selection: Option<&ResultSelection>,
+        filter: Option<&SearchFilter>,
+    ) -> Vec<SearchResult> {
         let result_selection = result_selection.unwrap_or(&ResultSelection::LastPreferFull);
-        let max_len = max_len.unwrap_or(self.tree_depth);
+        let max_len = max_len.unwrap_or(self.tree_depth).max(1);
+        let token_count = slices.len();
+        let window_lengths = window_lengths(&offsets, sentences, max_len);
 
-        let (mut slices, mut offsets) = self.tokenize(text);
+        let filter = filter.map(|filter| {
+            let language = match &filter.language {
+                Some(language) => Some(language.as_str()),
+                None if self.filter_lists.detect_language => self.filter_lists.identify(&slices),
+                None => None,
+            };
+            filter.compile(
+                &self.tokenizer,
+                language.and_then(|language| self.filter_lists.get(language)),
+                &self.hierarchy,
+            )
+        });
 
         // Pad the slices and their offsets to include the last words
         slices.extend(vec![String::new(); max_len]);
@@ -324,7 +893,8 @@ impl HashMapSearchTree {
 
         let mut results = slices
             .par_windows(max_len)
-            .map(|slice| self.traverse(slice))
+            .zip(window_lengths.par_iter())
+            .map(|(slice, length)| self.traverse(&slice[..*length], filter.as_ref()))
             .zip(offsets.par_windows(max_len))
             .filter_map(|(result, offsets)| result.map_or(None, |result| Some((result, offsets))))
             .filter_map(|(result, offsets)| {
@@ -387,11 +957,65 @@ impl HashMapSearchTree {
                             end,
                         )]
                     }
+                    ResultSelection::TopK(k) => {
+                        let result = results.last().unwrap();
+                        let end = offsets[result.search_terms.len() - 1].1;
+                        let mtches = result
+                            .get_search_results()
+                            .into_iter()
+                            .sorted_by(|a, b| b.weight.cmp(&a.weight).then(a.cmp(b)))
+                            .unique_by(|mtch| mtch.match_label.clone())
+                            .take(*k)
+                            .collect();
+                        vec![(result.get_search_term_string(), mtches, start, end)]
+                    }
                 }
             })
             .flatten()
             // .map(|(s, mtches, a, b)| (s, mtches.into_iter().sorted().collect::<Vec<&Match>>(), a, b))
-            .collect::<Vec<(String, Vec<Match>, usize, usize)>>();
+            .collect::<Vec<SearchResult>>();
+
+        if let Some(decompounder) = &self.decompounder {
+            let compounds = self.search_compounds(decompounder, &slices, &offsets, filter.as_ref());
+            results = merge_results(results, compounds);
+        }
+
+        if !self.context_rules.is_empty() {
+            results = self.apply_context_rules(results, &slices, &offsets);
+        }
+
+        if !self.regex_entries.is_empty() {
+            let mut regex_results = self.search_regex(text, filter.as_ref());
+            if let Some(sentences) = sentences {
+                regex_results.retain(|(_, _, start, end)| {
+                    sentence_segment(sentences, *start) == sentence_segment(sentences, end - 1)
+                });
+            }
+            results = merge_results(results, regex_results);
+        }
+
+        if let Some(blockers) = &self.blockers {
+            let blocked: Vec<(usize, usize)> = blockers
+                .search_tokens(
+                    text,
+                    (
+                        slices[..token_count].to_vec(),
+                        offsets[..token_count].to_vec(),
+                    ),
+                    sentences,
+                    None,
+                    Some(&ResultSelection::All),
+                    None,
+                )
+                .into_iter()
+                .map(|(_, _, start, end)| (start, end))
+                .collect();
+            results.retain(|(_, _, start, end)| {
+                !blocked
+                    .iter()
+                    .any(|(blocked_start, blocked_end)| start < blocked_end && blocked_start < end)
+            });
+        }
 
         // results.dedup_by(|b, a| b.2 <= a.3);
         // TODO: This removes fully covered entities that end on the same character as their covering entities but not partial overlaps
@@ -400,16 +1024,275 @@ impl HashMapSearchTree {
         results
     }
 
-    pub(crate) fn traverse(&self, window: &[String]) -> Result<Vec<TraversalResult>, String> {
+    /// Looks up the parts of compound tokens. The offsets of the parts are only exact if the
+    /// normalized token has the same length as the original one, otherwise the parts are reported
+    /// with the offsets of the whole token.
+    fn search_compounds(
+        &self,
+        decompounder: &Decompounder,
+        slices: &[String],
+        offsets: &[(usize, usize)],
+        filter: Option<&CompiledFilter>,
+    ) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+        for (token, (start, end)) in slices.iter().zip(offsets) {
+            if token.is_empty() || self.search_map.contains_key(std::slice::from_ref(token)) {
+                continue;
+            }
+            let chars: Vec<char> = token.chars().collect();
+            let exact = end - start == chars.len();
+            for (part_start, part_end) in decompounder.split(token) {
+                let part: Vec<String> = vec![chars[part_start..part_end].iter().collect()];
+                if filter.is_some_and(|filter| filter.is_stopword(&part)) {
+                    continue;
+                }
+                let mtches: Vec<Match> = self
+                    .lookup(&part)
+                    .into_iter()
+                    .filter(|mtch| filter.is_none_or(|filter| filter.accepts(mtch)))
+                    .map(|mtch| Match {
+                        match_type: MatchType::Compound,
+                        ..mtch.clone()
+                    })
+                    .sorted()
+                    .dedup()
+                    .collect();
+                if mtches.is_empty() {
+                    continue;
+                }
+                let (part_start, part_end) = if exact {
+                    (start + part_start, start + part_end)
+                } else {
+                    (*start, *end)
+                };
+                results.push((part.join(" "), mtches, part_start, part_end));
+            }
+        }
+        results
+    }
+
+    /// Removes matches whose corpus has context rules that are violated by the tokens around the
+    /// match. Results without any remaining matches are removed.
+    fn apply_context_rules(
+        &self,
+        results: Vec<SearchResult>,
+        slices: &[String],
+        offsets: &[(usize, usize)],
+    ) -> Vec<SearchResult> {
+        // Exclude the padding
+        let tokens = &offsets[..slices.iter().take_while(|s| !s.is_empty()).count()];
+        results
+            .into_iter()
+            .filter_map(|(string, mtches, start, end)| {
+                // The tokens containing the start and end of the span, so that compound parts are
+                // checked against the context of their compound token
+                let first = tokens.partition_point(|offset| offset.1 <= start);
+                let last = tokens.partition_point(|offset| offset.0 < end);
+                if last <= first {
+                    return Some((string, mtches, start, end));
+                }
+                let left = &slices[..first];
+                let right = &slices[last..tokens.len()];
+                let mtches: Vec<Match> = mtches
+                    .into_iter()
+                    .filter(|mtch| {
+                        mtch.corpus
+                            .as_ref()
+                            .and_then(|corpus| self.context_rules.get(corpus))
+                            .is_none_or(|rules| rules.iter().all(|rule| rule.check(left, right)))
+                    })
+                    .collect();
+                if mtches.is_empty() {
+                    None
+                } else {
+                    Some((string, mtches, start, end))
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluates the regex entries on the original text. Matches of multiple entries on the same
+    /// span are combined into a single result.
+    fn search_regex(&self, text: &str, filter: Option<&CompiledFilter>) -> Vec<SearchResult> {
+        let char_starts: Vec<usize> = text.char_indices().map(|(idx, _)| idx).collect();
+        let to_char_offset = |byte_offset: usize| match char_starts.binary_search(&byte_offset) {
+            Ok(idx) | Err(idx) => idx,
+        };
+
+        let mut spans: HashMap<(usize, usize), (&str, Vec<Match>)> = HashMap::new();
+        for (regex, pattern, label) in &self.regex_entries {
+            for found in regex.find_iter(text) {
+                if found.as_str().is_empty() {
+                    continue;
+                }
+                let mtch = Match {
+                    match_type: MatchType::Regex,
+                    match_string: pattern.clone(),
+                    match_label: label.clone(),
+                    accepted_label: None,
+                    weight: None,
+                    corpus: None,
+                };
+                if filter.is_some_and(|filter| !filter.accepts(&mtch)) {
+                    continue;
+                }
+                spans
+                    .entry((to_char_offset(found.start()), to_char_offset(found.end())))
+                    .or_insert_with(|| (found.as_str(), Vec::new()))
+                    .1
+                    .push(mtch);
+            }
+        }
+        spans
+            .into_iter()
+            .map(|((start, end), (string, mtches))| {
+                (
+                    string.to_string(),
+                    mtches.into_iter().sorted().collect(),
+                    start,
+                    end,
+                )
+            })
+            .collect()
+    }
+
+    /// Resolves abbreviated mentions using earlier full mentions in the same document.
+    ///
+    /// An `Abbreviated` match is kept only if its abbreviated genus was the genus, i.e. the first
+    /// token, of an earlier span with a `Full` match. If none of the abbreviated matches of a span
+    /// can be resolved this way, all of them are kept.
+    pub fn resolve_abbreviations(&self, results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let mut full_genera: HashSet<String> = HashSet::new();
+        let mut expansions: HashMap<Arc<String>, Vec<String>> = HashMap::new();
+
+        results
+            .into_iter()
+            .map(|(string, mtches, start, end)| {
+                let segments: Vec<&str> = string.split(' ').collect();
+                let (abbreviated, mut resolved): (Vec<Match>, Vec<Match>) = mtches
+                    .into_iter()
+                    .partition(|mtch| mtch.match_type == MatchType::Abbreviated);
+
+                if resolved
+                    .iter()
+                    .any(|mtch| mtch.match_type == MatchType::Full)
+                {
+                    full_genera.insert(String::from(segments[0]));
+                }
+
+                let candidates: Vec<&Match> = abbreviated
+                    .iter()
+                    .filter(|mtch| {
+                        let expanded = expansions
+                            .entry(mtch.match_string.clone())
+                            .or_insert_with(|| self.tokenize(&mtch.match_string).0);
+                        // Only the genus is abbreviated
+                        expanded.first().is_some_and(|genus| {
+                            genus != segments[0] && full_genera.contains(genus)
+                        })
+                    })
+                    .collect();
+                if candidates.is_empty() {
+                    resolved.extend(abbreviated.iter().cloned());
+                } else {
+                    resolved.extend(candidates.into_iter().cloned());
+                }
+                (string, resolved, start, end)
+            })
+            .collect()
+    }
+
+    /// Replaces the labels of synonym matches with their accepted labels, removing any matches
+    /// that become duplicates.
+    pub fn accepted_only(&self, results: Vec<SearchResult>) -> Vec<SearchResult> {
+        results
+            .into_iter()
+            .map(|(string, mtches, start, end)| {
+                let mtches = mtches
+                    .into_iter()
+                    .map(|mtch| match mtch.accepted_label {
+                        None => mtch,
+                        Some(accepted_label) => Match {
+                            match_label: accepted_label,
+                            accepted_label: None,
+                            ..mtch
+                        },
+                    })
+                    .sorted()
+                    .dedup()
+                    .collect();
+                (string, mtches, start, end)
+            })
+            .collect()
+    }
+
+    /// Returns the matches of the given search terms, including the matches of pattern entries
+    /// whose wildcards cover any of the search terms.
+    fn lookup(&self, search_terms: &[String]) -> Vec<&Match> {
+        let mut search_results: Vec<&Match> = self
+            .search_map
+            .get(search_terms)
+            .into_iter()
+            .flatten()
+            .collect();
+        for mask in &self.wildcard_masks {
+            if mask.len() != search_terms.len()
+                || search_terms
+                    .iter()
+                    .zip(mask)
+                    .any(|(segment, wildcard)| *wildcard && segment.is_empty())
+            {
+                continue;
+            }
+            let key: Vec<String> = search_terms
+                .iter()
+                .zip(mask)
+                .map(|(segment, wildcard)| {
+                    if *wildcard {
+                        String::from(WILDCARD)
+                    } else {
+                        segment.clone()
+                    }
+                })
+                .collect();
+            if let Some(mtches) = self.search_map.get(key.as_slice()) {
+                search_results.extend(mtches);
+            }
+        }
+        search_results
+    }
+
+    pub(crate) fn traverse(
+        &self,
+        window: &[String],
+        filter: Option<&CompiledFilter>,
+    ) -> Result<Vec<TraversalResult<'_>>, String> {
         let mut results = Vec::new();
         for i in 0..window.len() {
             let search_terms = window[0..=i].to_vec();
-            if let Some(search_results) = self.search_map.get(&search_terms) {
-                results.push(TraversalResult {
-                    search_terms,
-                    search_results,
-                });
+            let search_results = self.lookup(&search_terms);
+            if search_results.is_empty() {
+                continue;
+            }
+            let search_results: Vec<&Match> = match filter {
+                None => search_results,
+                Some(filter) => {
+                    if filter.is_stopword(&search_terms) {
+                        continue;
+                    }
+                    search_results
+                        .into_iter()
+                        .filter(|mtch| filter.accepts(mtch))
+                        .collect()
+                }
+            };
+            if search_results.is_empty() {
+                continue;
             }
+            results.push(TraversalResult {
+                search_terms,
+                search_results,
+            });
         }
         if results.is_empty() {
             Err(String::from("No matches found"))
@@ -419,25 +1302,88 @@ impl HashMapSearchTree {
     }
 }
 
+/// Returns the number of tokens of each window that do not cross a sentence boundary, including
+/// the window after the last token. Tokens that start outside of all sentences are grouped by the
+/// gaps between the sentences.
+fn window_lengths(
+    offsets: &[(usize, usize)],
+    sentences: Option<&[(usize, usize)]>,
+    max_len: usize,
+) -> Vec<usize> {
+    let Some(sentences) = sentences else {
+        return vec![max_len; offsets.len() + 1];
+    };
+    let segments: Vec<usize> = offsets
+        .iter()
+        .map(|(start, _)| sentence_segment(sentences, *start))
+        .collect();
+    let mut lengths = vec![0; offsets.len() + 1];
+    for idx in (0..offsets.len()).rev() {
+        lengths[idx] = if segments.get(idx + 1) == Some(&segments[idx]) {
+            (lengths[idx + 1] + 1).min(max_len)
+        } else {
+            1
+        };
+    }
+    lengths
+}
+
+/// Returns the index of the segment that contains the given character offset, where sentences and
+/// the gaps between them are separate segments.
+fn sentence_segment(sentences: &[(usize, usize)], offset: usize) -> usize {
+    let idx = sentences.partition_point(|sentence| sentence.1 <= offset);
+    let inside = sentences
+        .get(idx)
+        .is_some_and(|sentence| sentence.0 <= offset);
+    2 * idx + usize::from(inside)
+}
+
+/// Merges two lists of search results into a single list ordered by begin and end offsets,
+/// combining the matches of results with the same span.
+fn merge_results(results: Vec<SearchResult>, other: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut merged: Vec<SearchResult> = Vec::with_capacity(results.len() + other.len());
+    for result in results
+        .into_iter()
+        .chain(other)
+        .sorted_by_key(|result| (result.2, result.3))
+    {
+        match merged.last_mut() {
+            Some(last) if last.2 == result.2 && last.3 == result.3 => {
+                last.1.extend(result.1);
+                last.1.sort();
+                last.1.dedup();
+            }
+            _ => merged.push(result),
+        }
+    }
+    merged
+}
+
 pub struct TraversalResult<'a> {
     search_terms: Vec<String>,
-    search_results: &'a HashSet<Match>,
+    search_results: Vec<&'a Match>,
 }
 
 impl TraversalResult<'_> {
     fn get_search_term_string(&self) -> String {
         self.search_terms.join(" ")
     }
+    /// The matches of the traversal result, sorted so that the order of the results does not
+    /// depend on the iteration order of the underlying sets.
     fn get_search_results(&self) -> Vec<Match> {
-        self.search_results.iter().cloned().collect()
-    }
+        self.search_results
+            .iter()
+            .map(|mtch| (*mtch).clone())
+            .sorted()
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use itertools::Itertools;
-
     use super::*;
+    use crate::context::ContextDirection;
+    use crate::variants::TaxonomicNameGenerator;
 
     #[test]
     fn test_sample() {
@@ -556,4 +1502,860 @@ mod test {
             vec!["uri:example", "uri:phrase", "uri:phrase"]
         );
     }
+
+    #[test]
+    fn test_resolve_abbreviations() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Puffinus".to_string(), "uri:puffinus".to_string()),
+            (
+                "Puffinus puffinus".to_string(),
+                "uri:puffinus_puffinus".to_string(),
+            ),
+            (
+                "Procellaria puffinus".to_string(),
+                "uri:procellaria_puffinus".to_string(),
+            ),
+        ];
+        tree.load(entries, false, 0, 0, true);
+        let tree = tree;
+
+        let results = tree.search("Bei P. puffinus", Some(2), None);
+        let results = tree.resolve_abbreviations(results);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.len(), 2);
+
+        let results = tree.search("Puffinus puffinus brütet hier. P. puffinus", Some(2), None);
+        let results = tree.resolve_abbreviations(results);
+        println!("{results:?}");
+        let last = results.last().unwrap();
+        assert_eq!(last.0, "p puffinus");
+        assert_eq!(last.1.len(), 1);
+        assert_eq!(&*last.1[0].match_label, "uri:puffinus_puffinus");
+
+        // Epithets of earlier names are not genera
+        let results = tree.search(
+            "Procellaria puffinus brütet hier. P. puffinus",
+            Some(2),
+            None,
+        );
+        let results = tree.resolve_abbreviations(results);
+        let last = results.last().unwrap();
+        assert_eq!(last.0, "p puffinus");
+        assert_eq!(last.1.len(), 1);
+        assert_eq!(&*last.1[0].match_label, "uri:procellaria_puffinus");
+    }
+
+    #[test]
+    fn test_accepted_labels() {
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            CorpusRow {
+                search_term: "Morus bassanus".to_string(),
+                label: "gbif:2481069".to_string(),
+                ..Default::default()
+            },
+            CorpusRow {
+                search_term: "Sula bassana".to_string(),
+                label: "gbif:8137022".to_string(),
+                accepted_label: Some("gbif:2481069".to_string()),
+                ..Default::default()
+            },
+        ];
+        tree.load_rows(rows, &[]);
+        let tree = tree;
+
+        let results = tree.search("Sula bassana und Morus bassanus", None, None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(&*results[0].1[0].match_label, "gbif:8137022");
+        assert_eq!(
+            results[0].1[0]
+                .accepted_label
+                .as_deref()
+                .map(String::as_str),
+            Some("gbif:2481069")
+        );
+        assert_eq!(results[1].1[0].accepted_label, None);
+
+        let results = tree.accepted_only(results);
+        assert!(results.iter().all(|(_, mtches, _, _)| mtches.len() == 1
+            && mtches[0].match_label.as_str() == "gbif:2481069"
+            && mtches[0].accepted_label.is_none()));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            CorpusRow {
+                search_term: "Morus bassanus".to_string(),
+                label: "gbif:2481069".to_string(),
+                ..Default::default()
+            },
+            CorpusRow {
+                search_term: "Sula bassana".to_string(),
+                label: "gbif:8137022".to_string(),
+                accepted_label: Some("gbif:2481069".to_string()),
+                ..Default::default()
+            },
+        ];
+        tree.load_rows(rows, &[Box::new(AbbreviationGenerator {})]);
+        tree.build_label_index();
+        let tree = tree;
+
+        let (segments, mtches) = tree.lookup_term("SULA  Bassana");
+        assert_eq!(segments, vec!["sula", "bassana"]);
+        assert_eq!(mtches.len(), 1);
+        assert_eq!(mtches[0].match_type, MatchType::Full);
+        assert_eq!(&*mtches[0].match_label, "gbif:8137022");
+        let (_, mtches) = tree.lookup_term("S. bassana");
+        assert_eq!(mtches.len(), 1);
+        assert_eq!(mtches[0].match_type, MatchType::Abbreviated);
+        assert!(tree.lookup_term("Sula").1.is_empty());
+
+        let entries: Vec<(String, MatchType, &str)> = tree
+            .lookup_label("gbif:2481069")
+            .into_iter()
+            .map(|(search_term, mtch)| {
+                (
+                    search_term,
+                    mtch.match_type.clone(),
+                    mtch.match_label.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "m bassanus".to_string(),
+                    MatchType::Abbreviated,
+                    "gbif:2481069"
+                ),
+                (
+                    "morus bassanus".to_string(),
+                    MatchType::Full,
+                    "gbif:2481069"
+                ),
+                (
+                    "s bassana".to_string(),
+                    MatchType::Abbreviated,
+                    "gbif:8137022"
+                ),
+                ("sula bassana".to_string(), MatchType::Full, "gbif:8137022"),
+            ]
+        );
+        assert_eq!(tree.lookup_label("gbif:8137022").len(), 2);
+        assert!(tree.lookup_label("gbif:unknown").is_empty());
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            CorpusRow {
+                search_term: "Morus bassanus".to_string(),
+                label: "gbif:2481069".to_string(),
+                parent_label: Some("gbif:sulidae".to_string()),
+                ..Default::default()
+            },
+            CorpusRow {
+                search_term: "Sula bassana".to_string(),
+                label: "gbif:8137022".to_string(),
+                accepted_label: Some("gbif:2481069".to_string()),
+                ..Default::default()
+            },
+            CorpusRow {
+                search_term: "Homo sapiens".to_string(),
+                label: "gbif:2436436".to_string(),
+                parent_label: Some("gbif:hominidae".to_string()),
+                ..Default::default()
+            },
+        ];
+        tree.load_rows(rows, &[]);
+        tree.add_hierarchy(vec![
+            ("gbif:sulidae".to_string(), "gbif:aves".to_string()),
+            ("gbif:hominidae".to_string(), "gbif:mammalia".to_string()),
+        ]);
+        let tree = tree;
+
+        let text = "Sula bassana, Homo sapiens";
+        let results = tree.search(text, None, None);
+        assert_eq!(results.len(), 2);
+        let ancestors: Vec<String> = tree
+            .ancestors(&results[0].1[0])
+            .iter()
+            .map(|label| label.to_string())
+            .collect();
+        assert_eq!(ancestors, vec!["gbif:sulidae", "gbif:aves"]);
+
+        let filter = SearchFilter {
+            ancestor_labels: Some(vec!["gbif:aves".to_string()]),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter(text, None, None, Some(&filter));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "sula bassana");
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            ("Basstölpel", "gbif:2481069", Some(9000)),
+            ("Basstölpel", "wiki:basstoelpel", None),
+            ("Basstölpel", "gbif:8137022", Some(12)),
+        ]
+        .into_iter()
+        .map(|(term, label, weight)| CorpusRow {
+            search_term: term.to_string(),
+            label: label.to_string(),
+            weight,
+            ..Default::default()
+        })
+        .collect();
+        tree.load_rows(rows, &[]);
+        let tree = tree;
+
+        let results = tree.search("Ein Basstölpel", None, Some(&ResultSelection::TopK(2)));
+        assert_eq!(results.len(), 1);
+        let labels: Vec<&str> = results[0]
+            .1
+            .iter()
+            .map(|mtch| mtch.match_label.as_str())
+            .collect();
+        assert_eq!(labels, vec!["gbif:2481069", "gbif:8137022"]);
+
+        // Weights belong to the rows, not to their labels
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![("Sula".to_string(), "gbif:2481069".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        let rows = vec![
+            ("Basstölpel", "gbif:2481069", Some(9000)),
+            ("Sula bassana", "gbif:2481069", Some(12)),
+        ]
+        .into_iter()
+        .map(|(term, label, weight)| CorpusRow {
+            search_term: term.to_string(),
+            label: label.to_string(),
+            weight,
+            ..Default::default()
+        })
+        .collect();
+        tree.load_rows(rows, &[]);
+        let tree = tree;
+
+        let weights: Vec<Option<i64>> = tree
+            .search("Basstölpel, Sula bassana, Sula", None, None)
+            .iter()
+            .map(|(_, mtches, _, _)| mtches[0].weight)
+            .collect();
+        assert_eq!(weights, vec![Some(9000), Some(12), None]);
+
+        // Each label takes a single slot with its highest weighted match
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            ("Puffinus puffinus", "uri:a", 10),
+            ("Puffinus puffinus (Brünnich, 1764)", "uri:a", 10),
+            ("Puffinus puffinus", "uri:b", 5),
+        ]
+        .into_iter()
+        .map(|(term, label, weight)| CorpusRow {
+            search_term: term.to_string(),
+            label: label.to_string(),
+            weight: Some(weight),
+            ..Default::default()
+        })
+        .collect();
+        let generators: Vec<Box<dyn VariantGenerator>> =
+            vec![Box::new(TaxonomicNameGenerator::default())];
+        tree.load_rows(rows, &generators);
+        let tree = tree;
+
+        let results = tree.search("Puffinus puffinus", None, Some(&ResultSelection::TopK(2)));
+        let labels: Vec<(&str, MatchType)> = results[0]
+            .1
+            .iter()
+            .map(|mtch| (mtch.match_label.as_str(), mtch.match_type.clone()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![("uri:a", MatchType::Full), ("uri:b", MatchType::Full)]
+        );
+    }
+
+    #[test]
+    fn test_patterns() {
+        let mut tree = HashMapSearchTree::default();
+        let rows = vec![
+            ("Puffinus [sp|spec|spp]", "gbif:puffinus"),
+            ("Sula ? bassana", "gbif:sula_bassana"),
+        ]
+        .into_iter()
+        .map(|(term, label)| CorpusRow {
+            search_term: term.to_string(),
+            label: label.to_string(),
+            pattern: true,
+            ..Default::default()
+        })
+        .collect();
+        tree.load_rows(rows, &[]);
+        let tree = tree;
+
+        let results = tree.search(
+            "Puffinus spp. und Sula bassana, Sula (Morus) bassana, Sula",
+            None,
+            None,
+        );
+        let strings: Vec<&str> = results.iter().map(|result| result.0.as_str()).collect();
+        assert_eq!(
+            strings,
+            vec!["puffinus spp", "sula bassana", "sula morus bassana"]
+        );
+        assert!(results
+            .iter()
+            .all(|result| result.1[0].match_type == MatchType::Full));
+    }
+
+    #[test]
+    fn test_regex_entries() {
+        let mut tree = HashMapSearchTree::default();
+        tree.load(
+            vec![("Basstölpel".to_string(), "gbif:2481069".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        tree.add_regex(r"\b\d{3,4} m\b", "altitude").unwrap();
+        tree.add_regex(r"\b1[89]\d\d\b", "year").unwrap();
+        assert!(tree.add_regex(r"(", "invalid").is_err());
+        let tree = tree;
+
+        let text = "Ein Basstölpel, 1887 auf 1200 m gesichtet.";
+        let results = tree.search(text, None, None);
+        let spans: Vec<(&str, usize, usize)> = results
+            .iter()
+            .map(|(string, _, start, end)| (string.as_str(), *start, *end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![("basstölpel", 4, 14), ("1887", 16, 20), ("1200 m", 25, 31)]
+        );
+        assert_eq!(results[2].1[0].match_type, MatchType::Regex);
+        assert_eq!(&*results[2].1[0].match_label, "altitude");
+
+        let filter = SearchFilter {
+            match_types: Some(vec![MatchType::Full]),
+            ..Default::default()
+        };
+        assert_eq!(
+            tree.search_with_filter(text, None, None, Some(&filter))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_blockers() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Sula".to_string(), "gbif:sula".to_string()),
+            ("Art".to_string(), "uri:art".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, false);
+        tree.add_blockers(&["Herr Sula".to_string(), "die Art".to_string()]);
+        let tree = tree;
+
+        let results = tree.search("Herr Sula beobachtete die Art Sula bassana.", None, None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "sula");
+        assert_eq!(results[0].2, 30);
+    }
+
+    #[test]
+    fn test_context_rules() {
+        let mut tree = HashMapSearchTree::default();
+        tree.set_corpus(Some("genera"));
+        tree.load(
+            vec![("Sula".to_string(), "gbif:sula".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        tree.set_corpus(Some("species"));
+        tree.load(
+            vec![("Sula bassana".to_string(), "gbif:sula_bassana".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        tree.set_corpus(None);
+        tree.add_context_rules(
+            "genera",
+            &[
+                ContextRule {
+                    required: Some(vec!["Gattung".to_string()]),
+                    window: Some(2),
+                    direction: Some(ContextDirection::Left),
+                    ..Default::default()
+                },
+                ContextRule {
+                    forbidden: Some(vec!["Herr".to_string(), "Frau".to_string()]),
+                    window: Some(1),
+                    ..Default::default()
+                },
+            ],
+        );
+        tree.add_context_rules(
+            "species",
+            &[ContextRule {
+                forbidden: Some(vec!["Frau".to_string()]),
+                window: Some(1),
+                direction: Some(ContextDirection::Left),
+                ..Default::default()
+            }],
+        );
+        let tree = tree;
+
+        let strings = |text: &str| -> Vec<String> {
+            tree.search(text, None, None)
+                .into_iter()
+                .map(|result| result.0)
+                .collect()
+        };
+        assert_eq!(strings("Die Gattung Sula"), vec!["sula"]);
+        assert_eq!(strings("Die Sula"), Vec::<String>::new());
+        assert_eq!(strings("Gattung: Herr Sula"), Vec::<String>::new());
+        assert_eq!(strings("Sula bassana"), vec!["sula bassana"]);
+        assert_eq!(strings("Frau Sula bassana"), Vec::<String>::new());
+        assert_eq!(
+            strings("Tölpel der Gattung Sula, Sula bassana"),
+            vec!["sula", "sula bassana"]
+        );
+    }
+
+    #[test]
+    fn test_decompound() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Tölpel".to_string(), "gbif:sulidae".to_string()),
+            (
+                "Sturmtaucher".to_string(),
+                "gbif:procellariidae".to_string(),
+            ),
+            ("Basstölpel".to_string(), "gbif:2481069".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, false);
+        tree.set_decompound(true);
+        let tree = tree;
+
+        let results = tree.search(
+            "Die Tölpelkolonie am Sturmtaucherbrutplatz, ein Basstölpel.",
+            None,
+            None,
+        );
+        let spans: Vec<(&str, MatchType, usize, usize)> = results
+            .iter()
+            .map(|(string, mtches, start, end)| {
+                (string.as_str(), mtches[0].match_type.clone(), *start, *end)
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("tölpel", MatchType::Compound, 4, 10),
+                ("sturmtaucher", MatchType::Compound, 21, 33),
+                ("basstölpel", MatchType::Full, 48, 58),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_corpora() {
+        let mut tree = HashMapSearchTree::default();
+        for corpus in ["gbif", "wikidata"] {
+            tree.set_corpus(Some(corpus));
+            tree.load(
+                vec![("Sula bassana".to_string(), "gbif:2481069".to_string())],
+                false,
+                0,
+                0,
+                false,
+            );
+        }
+        tree.set_corpus(None);
+        let tree = tree;
+
+        let results = tree.search("Sula bassana", None, None);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.len(), 1);
+        assert_eq!(
+            results[0].1[0].corpus.as_deref().map(String::as_str),
+            Some("gbif")
+        );
+    }
+
+    #[test]
+    fn test_decompound_context_rules() {
+        let mut tree = HashMapSearchTree::default();
+        tree.set_corpus(Some("families"));
+        tree.load(
+            vec![("Tölpel".to_string(), "gbif:sulidae".to_string())],
+            false,
+            0,
+            0,
+            false,
+        );
+        tree.set_corpus(None);
+        tree.add_context_rules(
+            "families",
+            &[ContextRule {
+                forbidden: Some(vec!["Herr".to_string()]),
+                window: Some(1),
+                direction: Some(ContextDirection::Left),
+                ..Default::default()
+            }],
+        );
+        tree.set_decompound(true);
+        let tree = tree;
+
+        let spans: Vec<(String, usize, usize)> = tree
+            .search("Die Tölpelkolonie und Herr Tölpelmann", None, None)
+            .into_iter()
+            .map(|(string, _, start, end)| (string, start, end))
+            .collect();
+        assert_eq!(spans, vec![("tölpel".to_string(), 4, 10)]);
+    }
+
+    #[test]
+    fn test_character_mode() {
+        let mut tree = HashMapSearchTree::default();
+        tree.set_character_mode(true);
+        let entries: Vec<(String, String)> = vec![
+            ("鲣鸟".to_string(), "gbif:sula".to_string()),
+            ("北方鲣鸟".to_string(), "gbif:2481069".to_string()),
+            ("カツオドリ".to_string(), "gbif:2480983".to_string()),
+            ("Sula bassana".to_string(), "gbif:sula_bassana".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, false);
+        let tree = tree;
+
+        let text = "我们看到了北方鲣鸟。カツオドリとSula bassana";
+        let results = tree.search(text, None, None);
+        let spans: Vec<(&str, usize, usize)> = results
+            .iter()
+            .map(|(string, _, start, end)| (string.as_str(), *start, *end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("北 方 鲣 鸟", 5, 9),
+                ("カ ツ オ ド リ", 10, 15),
+                ("sula bassana", 16, 28)
+            ]
+        );
+        let surface: String = text.chars().skip(5).take(4).collect();
+        assert_eq!(surface, "北方鲣鸟");
+    }
+
+    #[test]
+    fn test_search_pretokenized() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Sula bassana".to_string(), "gbif:sula_bassana".to_string()),
+            (
+                "Puffinus puffinus".to_string(),
+                "gbif:puffinus_puffinus".to_string(),
+            ),
+        ];
+        tree.load(entries, false, 0, 0, true);
+        let tree = tree;
+
+        let text = "Die Sula-bassana-Kolonie und Puffinus puffinus.";
+        let spans = |results: Vec<SearchResult>| -> Vec<(String, usize, usize)> {
+            results
+                .into_iter()
+                .map(|(string, _, start, end)| (string, start, end))
+                .collect()
+        };
+        assert_eq!(
+            spans(tree.search(text, None, None)),
+            vec![
+                ("sula bassana".to_string(), 4, 16),
+                ("puffinus puffinus".to_string(), 29, 46)
+            ]
+        );
+
+        // The compound is a single upstream token, so the entry must not match inside of it
+        let tokens = vec![(0, 3), (4, 24), (25, 28), (29, 37), (38, 46), (46, 47)];
+        assert_eq!(
+            spans(tree.search_pretokenized(text, &tokens, None, None, None)),
+            vec![("puffinus puffinus".to_string(), 29, 46)]
+        );
+
+        let tokens = vec![(4, 8), (9, 16), (29, 37), (38, 46), (47, 50)];
+        assert_eq!(
+            spans(tree.search_pretokenized(text, &tokens, None, None, None)),
+            vec![
+                ("sula bassana".to_string(), 4, 16),
+                ("puffinus puffinus".to_string(), 29, 46)
+            ]
+        );
+
+        // Punctuation attached to the tokens is trimmed
+        let text = "Art (Sula bassana), selten.";
+        let tokens = vec![(0, 3), (4, 9), (10, 19), (20, 27)];
+        assert_eq!(
+            spans(tree.search_pretokenized(text, &tokens, None, None, None)),
+            vec![("sula bassana".to_string(), 5, 17)]
+        );
+    }
+
+    #[test]
+    fn test_sentence_boundaries() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Sula bassana".to_string(), "gbif:sula_bassana".to_string()),
+            ("Puffinus sp".to_string(), "gbif:puffinus".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, true);
+
+        let text = "Wir sahen Sula. Bassana fehlte, Puffinus sp. Nov. nicht.";
+        let spans = |results: Vec<SearchResult>| -> Vec<(String, usize, usize)> {
+            results
+                .into_iter()
+                .map(|(string, _, start, end)| (string, start, end))
+                .collect()
+        };
+        assert_eq!(
+            spans(tree.search(text, None, None)),
+            vec![("puffinus sp".to_string(), 32, 43)]
+        );
+
+        let crossing = vec![
+            ("sula bassana".to_string(), 10, 23),
+            ("puffinus sp".to_string(), 32, 43),
+        ];
+        let results = tree.search_with_boundaries(
+            text,
+            None,
+            &SentenceBoundaries::Given(vec![(0, 56)]),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(spans(results), crossing);
+
+        tree.set_cross_sentences(true);
+        assert_eq!(spans(tree.search(text, None, None)), crossing);
+
+        let mut tree = HashMapSearchTree::default();
+        tree.add_regex(r"Sula\W+[Bb]assana", "gbif:sula_bassana")
+            .unwrap();
+        let results = tree.search("Wir sahen Sula. Bassana fehlte, Sula bassana.", None, None);
+        assert_eq!(spans(results), vec![("Sula bassana".to_string(), 32, 44)]);
+        tree.set_cross_sentences(true);
+        let results = tree.search("Wir sahen Sula. Bassana fehlte, Sula bassana.", None, None);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_search_with_filter() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Art".to_string(), "uri:art".to_string()),
+            ("Sula".to_string(), "gbif:sula".to_string()),
+            ("Sula bassana".to_string(), "gbif:sula_bassana".to_string()),
+            ("Sula bassana".to_string(), "wiki:sula_bassana".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, true);
+        let tree = tree;
+        let text = "Die Art Sula bassana";
+
+        let results = tree.search(text, None, None);
+        assert_eq!(results.len(), 2);
+
+        let filter = SearchFilter {
+            stopwords: Some(vec!["ART".to_string()]),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter(text, None, None, Some(&filter));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.len(), 2);
+
+        let filter = SearchFilter {
+            include_labels: Some(vec!["gbif:".to_string()]),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter(text, None, None, Some(&filter));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.len(), 1);
+        assert_eq!(&*results[0].1[0].match_label, "gbif:sula_bassana");
+
+        let filter = SearchFilter {
+            exclude_labels: Some(vec!["gbif:sula_bassana".to_string()]),
+            match_types: Some(vec![MatchType::Full]),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter(text, None, None, Some(&filter));
+        let labels: Vec<&str> = results
+            .iter()
+            .flat_map(|r| r.1.iter().map(|mtch| mtch.match_label.as_str()))
+            .collect();
+        assert_eq!(labels, vec!["uri:art", "wiki:sula_bassana"]);
+
+        let filter = SearchFilter {
+            match_types: Some(vec![MatchType::Abbreviated]),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter("S. bassana", None, None, Some(&filter));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.len(), 2);
+    }
+
+    #[test]
+    fn test_language_filter_lists() {
+        let mut tree = HashMapSearchTree::default();
+        let entries: Vec<(String, String)> = vec![
+            ("Die".to_string(), "uri:die".to_string()),
+            ("Sula".to_string(), "uri:sula".to_string()),
+        ];
+        tree.load(entries, false, 0, 0, false);
+        tree.add_filter_list(
+            "de",
+            &["der".to_string(), "die".to_string(), "und".to_string()],
+        );
+        tree.add_filter_list("en", &["the".to_string(), "and".to_string()]);
+        let tree = tree;
+
+        let german = SearchFilter {
+            language: Some("de".to_string()),
+            ..Default::default()
+        };
+        let english = SearchFilter {
+            language: Some("en".to_string()),
+            ..Default::default()
+        };
+        let results = tree.search_with_filter("Die Sula", None, None, Some(&german));
+        assert_eq!(results.len(), 1);
+        let results = tree.search_with_filter("The die and Sula", None, None, Some(&english));
+        assert_eq!(results.len(), 2);
+
+        let unknown = SearchFilter::default();
+        let results = tree.search_with_filter("Die und der Sula", None, None, Some(&unknown));
+        assert_eq!(results.len(), 2);
+
+        let mut tree = tree;
+        tree.set_detect_language(true);
+        let results = tree.search_with_filter("Die und der Sula", None, None, Some(&unknown));
+        assert_eq!(results.len(), 1);
+        let results = tree.search_with_filter("The die and Sula", None, None, Some(&unknown));
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_prune() {
+        let entries: Vec<(String, String)> = vec![
+            ("Sula bassana".to_string(), "uri:sula_bassana".to_string()),
+            (
+                "Sula leucogaster".to_string(),
+                "uri:sula_leucogaster".to_string(),
+            ),
+            ("S leucogaster".to_string(), "uri:s_leucogaster".to_string()),
+            ("Die Art".to_string(), "uri:die_art".to_string()),
+        ];
+        let mut tree = HashMapSearchTree::default();
+        tree.load(entries.clone(), false, 0, 0, true);
+        let pruned = tree.prune(
+            &PruningOptions {
+                full_collisions: Some(true),
+                ..Default::default()
+            },
+            &None,
+        );
+        assert_eq!(pruned, 1);
+        let results = tree.search("S. leucogaster", None, None);
+        assert_eq!(results[0].1.len(), 1);
+        assert_eq!(&*results[0].1[0].match_label, "uri:s_leucogaster");
+
+        let mut tree = HashMapSearchTree::default();
+        tree.load(entries.clone(), false, 0, 0, true);
+        let pruned = tree.prune(
+            &PruningOptions {
+                filter_words: Some(true),
+                ..Default::default()
+            },
+            &Some(vec!["d".to_string(), "art".to_string()]),
+        );
+        assert_eq!(pruned, 1);
+        assert!(tree.search("D. Art", None, None).is_empty());
+
+        let mut entries = entries;
+        entries.push((
+            "Sterna leucogaster".to_string(),
+            "uri:sterna_leucogaster".to_string(),
+        ));
+        let mut tree = HashMapSearchTree::default();
+        tree.load(entries, false, 0, 0, true);
+        let pruned = tree.prune(
+            &PruningOptions {
+                max_labels: Some(1),
+                ..Default::default()
+            },
+            &None,
+        );
+        assert_eq!(pruned, 3);
+        let results = tree.search("S. bassana", None, None);
+        assert_eq!(results.len(), 1);
+        assert!(tree.search("S. leucogaster", None, None)[0]
+            .1
+            .iter()
+            .all(|mtch| mtch.match_type == MatchType::Full));
+    }
+
+    #[test]
+    fn test_spelling_rules() {
+        let mut tree = HashMapSearchTree::default();
+        tree.set_spelling_rules(vec![
+            SpellingRule {
+                pattern: regex::Regex::new("ſ").unwrap(),
+                replacement: "s".to_string(),
+            },
+            SpellingRule {
+                pattern: regex::Regex::new("th").unwrap(),
+                replacement: "t".to_string(),
+            },
+            SpellingRule {
+                pattern: regex::Regex::new("ß").unwrap(),
+                replacement: "ss".to_string(),
+            },
+        ]);
+        let entries: Vec<(String, String)> = vec![
+            ("Tier".to_string(), "uri:tier".to_string()),
+            ("Basstölpel".to_string(), "uri:basstoelpel".to_string()),
+            (
+                "Grosser Sturmtaucher".to_string(),
+                "uri:sturmtaucher".to_string(),
+            ),
+        ];
+        tree.load(entries, false, 0, 0, false);
+        let tree = tree;
+
+        let results = tree.search("Das Thier, ein Baſstölpel", None, None);
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].2, results[0].3), (4, 9));
+        assert_eq!((results[1].2, results[1].3), (15, 25));
+
+        let results = tree.search("Der Großer Sturmtaucher", None, None);
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].2, results[0].3), (4, 23));
+    }
 }
@@ -7,21 +7,24 @@ use std::str::FromStr;
 
 use anyhow::anyhow;
 use anyhow::Context;
-use csv::{ReaderBuilder, Trim};
+use csv::{ReaderBuilder, StringRecord, Trim};
 use flate2::bufread::GzDecoder;
 use glob::glob;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokenizers::normalizers::Sequence as NormalizerSequence;
 use tokenizers::normalizers::{Lowercase, NFKC};
 use tokenizers::pre_tokenizers::punctuation::Punctuation;
 use tokenizers::pre_tokenizers::sequence::Sequence as PreTokenizerSequence;
 use tokenizers::pre_tokenizers::whitespace::Whitespace;
+use tokenizers::tokenizer::normalizer::Range;
 use tokenizers::{
-    Normalizer, NormalizerWrapper, OffsetReferential, OffsetType, PreTokenizedString, PreTokenizer,
-    PreTokenizerWrapper, SplitDelimiterBehavior,
+    NormalizedString, Normalizer, NormalizerWrapper, OffsetReferential, OffsetType,
+    PreTokenizedString, PreTokenizer, PreTokenizerWrapper, SplitDelimiterBehavior,
 };
+use unicode_categories::UnicodeCategories;
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct CorpusFormat {
@@ -52,6 +55,18 @@ pub struct CorpusFormat {
     /// The label pattern string, i.e. the part of the label_format_string that is replaced with
     /// the label. Defaults to '{}'.
     pub label_format_pattern: Option<String>,
+    /// If given, the column index of the accepted label of synonyms. The accepted label is
+    /// formatted like the label.
+    pub accepted_label_column_idx: Option<usize>,
+    /// If given, the column index of the parent label, i.e. the family of a species. The parent
+    /// label is formatted like the label.
+    pub parent_label_column_idx: Option<usize>,
+    /// If given, the column index of an integer weight, i.e. an occurrence count or a curated
+    /// priority, which is used by the `TopK` result selection.
+    pub weight_column_idx: Option<usize>,
+    /// If true, the search terms are parsed as patterns with alternatives, optional tokens and
+    /// wildcards, see [`crate::pattern::expand_pattern`]. Defaults to false.
+    pub patterns: Option<bool>,
 }
 
 pub struct RobustCorpusFormat {
@@ -82,6 +97,18 @@ pub struct RobustCorpusFormat {
     /// The label pattern string, i.e. the part of the label_format_string that is replaced with
     /// the label. Defaults to '{}'.
     pub label_format_pattern: String,
+    /// If given, the column index of the accepted label of synonyms. The accepted label is
+    /// formatted like the label.
+    pub accepted_label_column_idx: Option<usize>,
+    /// If given, the column index of the parent label, i.e. the family of a species. The parent
+    /// label is formatted like the label.
+    pub parent_label_column_idx: Option<usize>,
+    /// If given, the column index of an integer weight, i.e. an occurrence count or a curated
+    /// priority, which is used by the `TopK` result selection.
+    pub weight_column_idx: Option<usize>,
+    /// If true, the search terms are parsed as patterns with alternatives, optional tokens and
+    /// wildcards, see [`crate::pattern::expand_pattern`]. Defaults to false.
+    pub patterns: bool,
 }
 
 impl Default for RobustCorpusFormat {
@@ -99,6 +126,10 @@ impl Default for RobustCorpusFormat {
             label_column_idx: 1,
             label_format_string: None,
             label_format_pattern: String::from("{}"),
+            accepted_label_column_idx: None,
+            parent_label_column_idx: None,
+            weight_column_idx: None,
+            patterns: false,
         }
     }
 }
@@ -131,6 +162,10 @@ impl TryFrom<CorpusFormat> for RobustCorpusFormat {
             label_format_pattern: format
                 .label_format_pattern
                 .unwrap_or(default.label_format_pattern),
+            accepted_label_column_idx: format.accepted_label_column_idx,
+            parent_label_column_idx: format.parent_label_column_idx,
+            weight_column_idx: format.weight_column_idx,
+            patterns: format.patterns.unwrap_or(default.patterns),
         };
         if let Some(label_format_string) = &robust_corpus_format.label_format_string {
             if !label_format_string.contains(&robust_corpus_format.label_format_pattern) {
@@ -143,6 +178,41 @@ impl TryFrom<CorpusFormat> for RobustCorpusFormat {
     }
 }
 
+impl RobustCorpusFormat {
+    /// Inserts the label into the label format string, if given.
+    pub fn format_label(&self, label: &str) -> String {
+        match self.label_format_string.as_ref() {
+            Some(format_string) => format_string.replace(&self.label_format_pattern, label),
+            None => String::from(label),
+        }
+    }
+}
+
+/// A row of an input table: the search term, its label and the optional columns.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorpusRow {
+    pub search_term: String,
+    pub label: String,
+    /// The accepted label, if the label is a synonym.
+    pub accepted_label: Option<String>,
+    /// The label of the parent in the label hierarchy.
+    pub parent_label: Option<String>,
+    /// The weight of the entry, higher weights are preferred.
+    pub weight: Option<i64>,
+    /// If true, the search term is a pattern, see [`crate::pattern::expand_pattern`].
+    pub pattern: bool,
+}
+
+impl From<(String, String)> for CorpusRow {
+    fn from((search_term, label): (String, String)) -> Self {
+        CorpusRow {
+            search_term,
+            label,
+            ..Default::default()
+        }
+    }
+}
+
 pub fn read_lines(filename: &str) -> Vec<String> {
     let extension = match Path::new(filename).extension() {
         None => "",
@@ -165,7 +235,7 @@ pub fn read_lines(filename: &str) -> Vec<String> {
     }
 }
 
-pub fn read_csv(filename: &str, format: &CorpusFormat) -> anyhow::Result<Vec<(String, String)>> {
+pub fn read_csv(filename: &str, format: &CorpusFormat) -> anyhow::Result<Vec<CorpusRow>> {
     let extension = match Path::new(filename).extension() {
         None => "",
         Some(ext) => ext.to_str().unwrap(),
@@ -192,7 +262,11 @@ pub fn read_csv(filename: &str, format: &CorpusFormat) -> anyhow::Result<Vec<(St
 
     let search_term_column_idx = format.search_term_column_idx;
     let label_column_idx = format.label_column_idx;
-    let label_format_pattern = format.label_format_pattern;
+    let optional_label = |row: &StringRecord, idx: Option<usize>, label: &str| {
+        idx.and_then(|idx| row.get(idx))
+            .filter(|value| !value.is_empty() && *value != label)
+            .map(|value| format.format_label(value))
+    };
 
     let reader = ReaderBuilder::new()
         .comment(format.comment)
@@ -212,24 +286,47 @@ pub fn read_csv(filename: &str, format: &CorpusFormat) -> anyhow::Result<Vec<(St
             }
             match (row.get(search_term_column_idx), row.get(label_column_idx)) {
                 (None, None) | (None, _) | (_, None) => None,
-                (Some(search_term), Some(label)) => {
-                    Some((String::from(search_term), String::from(label)))
-                }
-            }
-        })
-        .map(|(search_term, label)| {
-            if let Some(format_string) = format.label_format_string.as_ref() {
-                return (
-                    search_term,
-                    format_string.replace(&label_format_pattern, &label),
-                );
+                (Some(search_term), Some(label)) => Some(CorpusRow {
+                    search_term: String::from(search_term),
+                    label: format.format_label(label),
+                    accepted_label: optional_label(&row, format.accepted_label_column_idx, label),
+                    parent_label: optional_label(&row, format.parent_label_column_idx, label),
+                    weight: format
+                        .weight_column_idx
+                        .and_then(|idx| row.get(idx))
+                        .and_then(|weight| weight.parse::<i64>().ok()),
+                    pattern: format.patterns,
+                }),
             }
-            (search_term, label)
         })
-        .collect::<Vec<(String, String)>>();
+        .collect::<Vec<CorpusRow>>();
     Ok(reader)
 }
 
+/// Reads a label hierarchy file of tab-separated label and parent label pairs. Both labels are
+/// formatted with the label format of the given corpus format.
+pub fn read_hierarchy(
+    filename: &str,
+    format: &Option<CorpusFormat>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let format = RobustCorpusFormat::try_from(format.clone().unwrap_or_default())
+        .context("Failed to convert corpus format")?;
+    read_lines(filename)
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (label, parent_label) = line
+                .split_once('\t')
+                .context(format!("Invalid hierarchy line '{line}'"))?;
+            Ok((
+                format.format_label(label.trim()),
+                format.format_label(parent_label.trim()),
+            ))
+        })
+        .collect()
+}
+
 #[must_use]
 pub fn get_files(root_path: &str) -> Vec<String> {
     let mut files = glob(root_path)
@@ -285,7 +382,7 @@ pub fn parse_files(
     pb: Option<&ProgressBar>,
     format: &Option<CorpusFormat>,
     filter_list: &Option<Vec<String>>,
-) -> anyhow::Result<Vec<(String, String)>> {
+) -> anyhow::Result<Vec<CorpusRow>> {
     let format: CorpusFormat = match format {
         None => CorpusFormat::default(),
         Some(format) => format.clone(),
@@ -296,7 +393,7 @@ pub fn parse_files(
             .map(|s| s.to_lowercase())
             .collect::<HashSet<String>>()
     });
-    let parsed_files: Result<Vec<Vec<(String, String)>>, anyhow::Error> = files
+    let parsed_files: Result<Vec<Vec<CorpusRow>>, anyhow::Error> = files
         .par_iter()
         .map(|file| {
             let pairs = read_csv(file, &format)?;
@@ -309,27 +406,133 @@ pub fn parse_files(
     Ok(parsed_files?
         .into_iter()
         .flatten()
-        .filter(|(search_term, _)| {
-            filter_list.is_empty() || !filter_list.contains(&search_term.to_lowercase())
+        .filter(|row| {
+            filter_list.is_empty() || !filter_list.contains(&row.search_term.to_lowercase())
+        })
+        .collect::<Vec<CorpusRow>>())
+}
+
+/// A spelling rule that replaces all matches of the pattern in the normalized text, i.e. to map
+/// historical spellings like `thier` to their modern form `tier`.
+#[derive(Debug, Clone)]
+pub struct SpellingRule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+/// Reads spelling rules from a two-column TSV file of patterns and replacements. Empty lines and
+/// lines starting with `#` are ignored. As rules are applied after lowercasing, patterns should
+/// be lowercase.
+pub fn read_spelling_rules(filename: &str) -> anyhow::Result<Vec<SpellingRule>> {
+    read_lines(filename)
+        .iter()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (pattern, replacement) = line
+                .split_once('\t')
+                .context(format!("Invalid spelling rule '{line}'"))?;
+            Ok(SpellingRule {
+                pattern: Regex::new(pattern)
+                    .context(format!("Invalid spelling rule pattern '{pattern}'"))?,
+                replacement: String::from(replacement),
+            })
         })
-        .collect::<Vec<(String, String)>>())
+        .collect()
 }
 
-#[derive(Debug)]
+/// Replaces all matches of the rule in the normalized string. Unlike [`NormalizedString::replace`],
+/// the replacement is aligned with the replaced characters, so that the original offsets of the
+/// tokens are preserved.
+fn apply_spelling_rule(normalized: &mut NormalizedString, rule: &SpellingRule) {
+    let matches: Vec<(usize, usize)> = rule
+        .pattern
+        .find_iter(normalized.get())
+        .filter(|mtch| !mtch.is_empty())
+        .map(|mtch| (mtch.start(), mtch.end()))
+        .collect();
+    let replacement: Vec<char> = rule.replacement.chars().collect();
+
+    let mut shift: isize = 0;
+    for (start, end) in matches {
+        let range = (start as isize + shift) as usize..(end as isize + shift) as usize;
+        let removed = normalized.get()[range.clone()].chars().count();
+        let mut changes: Vec<(char, isize)> = replacement
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (*c, if i < removed { 0 } else { 1 }))
+            .collect();
+        let mut initial_offset = 0;
+        if replacement.len() < removed {
+            match changes.last_mut() {
+                None => initial_offset = removed,
+                Some(last) => last.1 = -((removed - replacement.len()) as isize),
+            }
+        }
+        normalized.transform_range(Range::Normalized(range), changes, initial_offset);
+        shift += rule.replacement.len() as isize - (end - start) as isize;
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Tokenizer {
     normalizer: NormalizerWrapper,
     pre_tokenizer: PreTokenizerWrapper,
+    spelling_rules: Vec<SpellingRule>,
+    character_mode: bool,
+}
+
+/// Returns true for the characters that the pre-tokenizer splits off as punctuation.
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || c.is_punctuation()
+}
+
+/// Returns true for characters of scripts that are written without whitespace between words, i.e.
+/// Chinese characters and Japanese kana.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}'
+    )
 }
 
 impl Tokenizer {
+    /// Sets the spelling rules that are applied after normalization. The original offsets of the
+    /// tokens are preserved.
+    pub fn set_spelling_rules(&mut self, spelling_rules: Vec<SpellingRule>) {
+        self.spelling_rules = spelling_rules;
+    }
+
+    /// If enabled, each CJK character is split into its own token, see [`is_cjk`], so entries and
+    /// texts without whitespace can be matched character by character.
+    pub fn set_character_mode(&mut self, character_mode: bool) {
+        self.character_mode = character_mode;
+    }
+
+    fn normalize(&self, normalized: &mut NormalizedString) -> tokenizers::Result<()> {
+        self.normalizer.normalize(normalized)?;
+        for rule in &self.spelling_rules {
+            apply_spelling_rule(normalized, rule);
+        }
+        Ok(())
+    }
+
     pub fn tokenize(&self, string: &str) -> TokensAndOffsets {
         let mut string = PreTokenizedString::from(string);
         string
-            .normalize(|s| self.normalizer.normalize(s))
+            .normalize(|s| self.normalize(s))
             .expect("Failed during normalization!");
         self.pre_tokenizer
             .pre_tokenize(&mut string)
             .expect("Failed during pre-tokenization!");
+        if self.character_mode {
+            string
+                .split(|_, s| s.split(is_cjk, SplitDelimiterBehavior::Isolated))
+                .expect("Failed during character tokenization!");
+        }
         let mut tokens = Vec::new();
         let mut offsets = Vec::new();
         for (slice, offset, _) in string.get_splits(OffsetReferential::Original, OffsetType::Char) {
@@ -339,6 +542,45 @@ impl Tokenizer {
         (tokens, offsets)
     }
 
+    /// Normalizes the given tokens of a pre-tokenized text without splitting them any further.
+    /// The tokens are given as sorted, non-overlapping character offsets into the text. Leading
+    /// and trailing whitespace and punctuation is trimmed like by the pre-tokenizer, i.e. from
+    /// `(Sula` or `bassana,`, and the offsets are adjusted accordingly. Tokens that are out of
+    /// bounds or empty after trimming or normalization are skipped.
+    pub fn normalize_tokens(&self, string: &str, tokens: &[(usize, usize)]) -> TokensAndOffsets {
+        let chars: Vec<char> = string.chars().collect();
+        let char_starts: Vec<usize> = string
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(std::iter::once(string.len()))
+            .collect();
+        let is_trimmed = |c: &char| c.is_whitespace() || is_punctuation(*c);
+        let mut slices = Vec::new();
+        let mut offsets = Vec::new();
+        for &(start, end) in tokens {
+            if start >= end || end > chars.len() {
+                continue;
+            }
+            let Some(first) = chars[start..end].iter().position(|c| !is_trimmed(c)) else {
+                continue;
+            };
+            let last = chars[start..end]
+                .iter()
+                .rposition(|c| !is_trimmed(c))
+                .unwrap();
+            let (start, end) = (start + first, start + last + 1);
+            let mut normalized =
+                NormalizedString::from(&string[char_starts[start]..char_starts[end]]);
+            self.normalize(&mut normalized)
+                .expect("Failed during normalization!");
+            if !normalized.is_empty() {
+                slices.push(String::from(normalized.get()));
+                offsets.push((start, end));
+            }
+        }
+        (slices, offsets)
+    }
+
     pub fn encode_batch(&self, inputs: &[&str]) -> Vec<TokensAndOffsets> {
         let pb = ProgressBar::new(inputs.len() as u64);
         pb.set_style(
@@ -368,6 +610,8 @@ impl Default for Tokenizer {
                 PreTokenizerWrapper::Punctuation(Punctuation::new(SplitDelimiterBehavior::Removed)),
                 PreTokenizerWrapper::Whitespace(Whitespace),
             ])),
+            spelling_rules: Vec::new(),
+            character_mode: false,
         }
     }
 }
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::tree::MatchType;
+use crate::util::{create_skip_grams, read_lines, Tokenizer};
+
+const DEFAULT_MAX_INFLECTIONS: usize = 32;
+
+/// A generated variant of an entry: its segments and the type of match it produces.
+pub type Variant = (Vec<String>, MatchType);
+
+/// Generates additional search keys for the entries of a corpus.
+pub trait VariantGenerator: Debug + Send + Sync {
+    /// The name of the generator, used for progress messages.
+    fn name(&self) -> &str;
+
+    /// Generates the variants for a single entry, given its original search term and its segments.
+    fn generate(
+        &self,
+        search_term: &str,
+        segments: &[String],
+        tokenizer: &Tokenizer,
+    ) -> Vec<Variant>;
+}
+
+/// Generates skip-grams by removing up to `max_skips` segments (never the first one) from entries
+/// with more than `min_length` segments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SkipGramGenerator {
+    pub min_length: i32,
+    pub max_skips: i32,
+}
+
+impl Default for SkipGramGenerator {
+    fn default() -> Self {
+        SkipGramGenerator {
+            min_length: 2,
+            max_skips: 2,
+        }
+    }
+}
+
+impl VariantGenerator for SkipGramGenerator {
+    fn name(&self) -> &str {
+        "skip_grams"
+    }
+
+    fn generate(&self, _: &str, segments: &[String], _: &Tokenizer) -> Vec<Variant> {
+        if segments.len() <= self.min_length as usize {
+            return Vec::new();
+        }
+        let mut deletes =
+            create_skip_grams(vec![segments.to_vec()], self.max_skips, self.min_length);
+        deletes.sort();
+        deletes.dedup();
+        deletes
+            .into_iter()
+            .map(|skip_gram| (skip_gram, MatchType::SkipGram))
+            .collect()
+    }
+}
+
+/// Generates abbreviations by replacing each but the last segment with its first character.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AbbreviationGenerator {}
+
+impl VariantGenerator for AbbreviationGenerator {
+    fn name(&self) -> &str {
+        "abbreviations"
+    }
+
+    fn generate(&self, _: &str, segments: &[String], _: &Tokenizer) -> Vec<Variant> {
+        if segments.len() <= 1 {
+            return Vec::new();
+        }
+        let mut variants = Vec::new();
+        for i in 0..(segments.len() - 1) {
+            let mut abbrv: Vec<String> = Vec::with_capacity(segments.len());
+            abbrv.extend_from_slice(&segments[0..i]);
+            abbrv.push(segments[i].chars().next().unwrap().to_string());
+            abbrv.extend_from_slice(&segments[(i + 1)..]);
+            variants.push((abbrv, MatchType::Abbreviated));
+        }
+        variants
+    }
+}
+
+/// Rank markers of infraspecific names, with the alternative spellings they are expanded to.
+const RANK_MARKERS: &[(&str, &[&str])] = &[
+    ("subsp", &["ssp"]),
+    ("ssp", &["subsp"]),
+    ("var", &[]),
+    ("subvar", &[]),
+    ("f", &["forma"]),
+    ("fo", &["f", "forma"]),
+    ("forma", &["f"]),
+    ("subf", &[]),
+];
+
+/// Lowercase name particles that start an author citation, i.e. `de Candolle`.
+const AUTHOR_PARTICLES: &[&str] = &[
+    "d'", "da", "de", "den", "der", "du", "ex", "in", "van", "von",
+];
+
+/// A scientific name split into its genus and its epithets, without any author citation.
+#[derive(Debug, PartialEq)]
+struct ScientificName<'a> {
+    genus: &'a str,
+    /// The epithets and rank markers, the latter without any trailing dot.
+    parts: Vec<(&'a str, bool)>,
+}
+
+impl<'a> ScientificName<'a> {
+    fn parse(search_term: &'a str) -> Option<Self> {
+        let words: Vec<&str> = search_term.split_whitespace().collect();
+        let genus = *words.first()?;
+        if !genus.chars().next()?.is_uppercase() || !genus.chars().all(char::is_alphabetic) {
+            return None;
+        }
+
+        let mut parts: Vec<(&str, bool)> = Vec::new();
+        let mut i = 1;
+        while i < words.len() {
+            let word = words[i];
+            let marker = word.trim_end_matches('.');
+            if RANK_MARKERS.iter().any(|(m, _)| *m == marker) {
+                parts.push((marker, true));
+            } else if parts.is_empty()
+                && word.starts_with('(')
+                && word.ends_with(')')
+                && words
+                    .get(i + 1)
+                    .is_some_and(|next| next.chars().next().is_some_and(char::is_lowercase))
+            {
+                // Skip infrageneric names, i.e. `Puffinus (Puffinus) puffinus`
+            } else if !AUTHOR_PARTICLES.contains(&word)
+                && word
+                    .chars()
+                    .all(|c| c.is_lowercase() && c.is_alphabetic() || c == '-')
+            {
+                parts.push((word, false));
+            } else {
+                break;
+            }
+            i += 1;
+        }
+
+        // Anything that remains must look like an author citation, otherwise this is not a
+        // scientific name, i.e. `Großer Sturmtaucher`. Citations without years or punctuation,
+        // i.e. `Linnaeus` or `L`, start with an uppercase word after at least one epithet.
+        let citation = words[i..].join(" ");
+        if !citation.is_empty()
+            && !citation.contains(|c: char| c.is_ascii_digit() || "(),.&".contains(c))
+            && !AUTHOR_PARTICLES.contains(&words[i])
+            && (parts.is_empty() || !words[i].chars().next().is_some_and(char::is_uppercase))
+        {
+            return None;
+        }
+
+        while parts.last().is_some_and(|(_, is_marker)| *is_marker) {
+            parts.pop();
+        }
+        Some(ScientificName { genus, parts })
+    }
+
+    fn epithets(&self) -> Vec<&'a str> {
+        self.parts
+            .iter()
+            .filter(|(_, is_marker)| !is_marker)
+            .map(|(part, _)| *part)
+            .collect()
+    }
+
+    fn initial(word: &str) -> String {
+        word.chars().take(1).collect()
+    }
+}
+
+/// Generates variants of scientific names, i.e. `Puffinus puffinus (Brünnich, 1764)` or
+/// `Homo sapiens subsp. sapiens`: the name without author citation, with alternative or without
+/// rank markers, reduced to a binomial and abbreviated as in `P. p. puffinus`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaxonomicNameGenerator {
+    /// If true, infraspecific names are reduced to their binomial. Defaults to true.
+    pub binomial: bool,
+    /// If true, abbreviations of the genus and species are generated. Defaults to true.
+    pub abbreviations: bool,
+}
+
+impl Default for TaxonomicNameGenerator {
+    fn default() -> Self {
+        TaxonomicNameGenerator {
+            binomial: true,
+            abbreviations: true,
+        }
+    }
+}
+
+impl VariantGenerator for TaxonomicNameGenerator {
+    fn name(&self) -> &str {
+        "taxonomic"
+    }
+
+    fn generate(
+        &self,
+        search_term: &str,
+        segments: &[String],
+        tokenizer: &Tokenizer,
+    ) -> Vec<Variant> {
+        let name = match ScientificName::parse(search_term) {
+            None => return Vec::new(),
+            Some(name) => name,
+        };
+
+        let mut candidates: Vec<(String, MatchType)> = Vec::new();
+        let canonical: Vec<&str> = std::iter::once(name.genus)
+            .chain(name.parts.iter().map(|(part, _)| *part))
+            .collect();
+        candidates.push((canonical.join(" "), MatchType::Canonical));
+        for (i, (part, is_marker)) in name.parts.iter().enumerate() {
+            if !is_marker {
+                continue;
+            }
+            let alternatives = RANK_MARKERS
+                .iter()
+                .find(|(marker, _)| marker == part)
+                .map_or(&[] as &[&str], |(_, alternatives)| *alternatives);
+            for alternative in alternatives {
+                let mut variant = canonical.clone();
+                variant[i + 1] = alternative;
+                candidates.push((variant.join(" "), MatchType::Canonical));
+            }
+        }
+
+        let epithets = name.epithets();
+        if epithets.len() < name.parts.len() {
+            let trinomial: Vec<&str> = std::iter::once(name.genus)
+                .chain(epithets.clone())
+                .collect();
+            candidates.push((trinomial.join(" "), MatchType::Trinomial));
+        }
+        if self.binomial && epithets.len() > 1 {
+            candidates.push((
+                format!("{} {}", name.genus, epithets[0]),
+                MatchType::Binomial,
+            ));
+        }
+        if self.abbreviations && !epithets.is_empty() {
+            candidates.push((
+                format!(
+                    "{} {}",
+                    ScientificName::initial(name.genus),
+                    epithets.join(" ")
+                ),
+                MatchType::TaxonAbbreviated,
+            ));
+            if epithets.len() > 1 {
+                let initials: Vec<String> = std::iter::once(name.genus)
+                    .chain(epithets[..epithets.len() - 1].iter().copied())
+                    .map(ScientificName::initial)
+                    .collect();
+                candidates.push((
+                    format!("{} {}", initials.join(" "), epithets[epithets.len() - 1]),
+                    MatchType::TaxonAbbreviated,
+                ));
+            }
+        }
+
+        let mut variants: Vec<Variant> = Vec::new();
+        for (candidate, match_type) in candidates {
+            let tokens = tokenizer.tokenize(&candidate).0;
+            if tokens != segments && !variants.iter().any(|(other, _)| other == &tokens) {
+                variants.push((tokens, match_type));
+            }
+        }
+        variants
+    }
+}
+
+/// A suffix rule for inflections: tokens ending in `ending` are inflected by replacing it with
+/// each of the `replacements`. An ending of `*` matches any token that no other rule matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InflectionRule {
+    /// If true, the rule applies to the last token of an entry, otherwise to all other tokens.
+    pub last: bool,
+    pub ending: String,
+    pub replacements: Vec<String>,
+}
+
+/// Generates inflected forms of (multi-word) vernacular names from suffix rules, i.e.
+/// `Großen Sturmtauchers` for `Großer Sturmtaucher`.
+#[derive(Debug, Clone)]
+pub struct InflectionGenerator {
+    rules: Vec<InflectionRule>,
+    /// The maximum number of inflected variants per entry.
+    max_variants: usize,
+}
+
+impl InflectionGenerator {
+    pub fn new(rules: Vec<InflectionRule>, max_variants: usize) -> Self {
+        InflectionGenerator {
+            rules,
+            max_variants,
+        }
+    }
+
+    /// Reads the rules from a three-column TSV file with the position (`last` or `other`), the
+    /// ending and the comma-separated replacements. Empty lines and lines starting with `#` are
+    /// ignored.
+    pub fn read_rules(filename: &str) -> anyhow::Result<Vec<InflectionRule>> {
+        read_lines(filename)
+            .iter()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() != 3 {
+                    return Err(anyhow!("Invalid inflection rule '{line}'"));
+                }
+                let last = match columns[0] {
+                    "last" => true,
+                    "other" => false,
+                    position => return Err(anyhow!("Invalid inflection position '{position}'")),
+                };
+                Ok(InflectionRule {
+                    last,
+                    ending: columns[1].to_lowercase(),
+                    replacements: columns[2]
+                        .split(',')
+                        .map(|replacement| replacement.trim().to_lowercase())
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the given token and all its inflected forms.
+    fn inflect(&self, token: &str, last: bool) -> Vec<String> {
+        let mut forms = vec![String::from(token)];
+        if !token.chars().all(char::is_alphabetic) {
+            return forms;
+        }
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| rule.last == last && rule.ending != "*" && token.ends_with(&rule.ending))
+            .max_by_key(|rule| rule.ending.len())
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|rule| rule.last == last && rule.ending == "*")
+            });
+        if let Some(rule) = rule {
+            let stem = match rule.ending.as_str() {
+                "*" => token,
+                ending => &token[..token.len() - ending.len()],
+            };
+            for replacement in &rule.replacements {
+                let form = format!("{stem}{replacement}");
+                if !forms.contains(&form) {
+                    forms.push(form);
+                }
+            }
+        }
+        forms
+    }
+}
+
+#[derive(Deserialize)]
+struct InflectionGeneratorConfig {
+    rules_path: String,
+    max_variants: Option<usize>,
+}
+
+impl VariantGenerator for InflectionGenerator {
+    fn name(&self) -> &str {
+        "inflections"
+    }
+
+    fn generate(&self, _: &str, segments: &[String], _: &Tokenizer) -> Vec<Variant> {
+        let last = segments.len().saturating_sub(1);
+        segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| self.inflect(segment, i == last))
+            .multi_cartesian_product()
+            .skip(1)
+            .take(self.max_variants)
+            .map(|variant| (variant, MatchType::Inflected))
+            .collect()
+    }
+}
+
+pub type GeneratorFactory =
+    Box<dyn Fn(&toml::value::Table) -> anyhow::Result<Box<dyn VariantGenerator>> + Send + Sync>;
+
+/// Builds variant generators from their configuration by name.
+///
+/// The generators are configured as an ordered list of tables with a `name` key, i.e.
+/// `generators = [{ name = "skip_grams", min_length = 2, max_skips = 2 }, { name = "abbreviations" }]`.
+/// All other keys are passed to the factory registered for that name.
+pub struct VariantGeneratorRegistry {
+    factories: HashMap<String, GeneratorFactory>,
+}
+
+impl VariantGeneratorRegistry {
+    /// Creates an empty registry without any generators.
+    pub fn empty() -> Self {
+        VariantGeneratorRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers a factory for the given generator name, replacing any previous factory.
+    pub fn register(&mut self, name: &str, factory: GeneratorFactory) {
+        self.factories.insert(String::from(name), factory);
+    }
+
+    /// Registers a generator that is deserialized from its configuration table.
+    pub fn register_deserializable<G>(&mut self, name: &str)
+    where
+        G: VariantGenerator + for<'de> Deserialize<'de> + 'static,
+    {
+        self.register(
+            name,
+            Box::new(|table| {
+                let generator: G = toml::Value::Table(table.clone()).try_into()?;
+                Ok(Box::new(generator))
+            }),
+        );
+    }
+
+    pub fn build(&self, config: &toml::value::Table) -> anyhow::Result<Box<dyn VariantGenerator>> {
+        let name = config
+            .get("name")
+            .and_then(toml::Value::as_str)
+            .context("A generator configuration requires a 'name'")?;
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown variant generator '{name}'"))?;
+        let mut options = config.clone();
+        options.remove("name");
+        factory(&options).context(format!("Failed to build variant generator '{name}'"))
+    }
+
+    pub fn build_all(
+        &self,
+        configs: &[toml::value::Table],
+    ) -> anyhow::Result<Vec<Box<dyn VariantGenerator>>> {
+        configs.iter().map(|config| self.build(config)).collect()
+    }
+}
+
+impl Default for VariantGeneratorRegistry {
+    fn default() -> Self {
+        let mut registry = VariantGeneratorRegistry::empty();
+        registry.register_deserializable::<SkipGramGenerator>("skip_grams");
+        registry.register_deserializable::<AbbreviationGenerator>("abbreviations");
+        registry.register_deserializable::<TaxonomicNameGenerator>("taxonomic");
+        registry.register(
+            "inflections",
+            Box::new(|table| {
+                let config: InflectionGeneratorConfig =
+                    toml::Value::Table(table.clone()).try_into()?;
+                let rules = InflectionGenerator::read_rules(&config.rules_path)?;
+                Ok(Box::new(InflectionGenerator::new(
+                    rules,
+                    config.max_variants.unwrap_or(DEFAULT_MAX_INFLECTIONS),
+                )))
+            }),
+        );
+        registry
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::HashMapSearchTree;
+
+    #[derive(Debug, Deserialize)]
+    struct SuffixGenerator {
+        suffix: String,
+    }
+
+    impl VariantGenerator for SuffixGenerator {
+        fn name(&self) -> &str {
+            "suffix"
+        }
+
+        fn generate(&self, _: &str, segments: &[String], _: &Tokenizer) -> Vec<Variant> {
+            let mut variant = segments.to_vec();
+            variant.push(self.suffix.clone());
+            vec![(variant, MatchType::None)]
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = VariantGeneratorRegistry::default();
+        registry.register_deserializable::<SuffixGenerator>("suffix");
+
+        let configs: Vec<toml::value::Table> = toml::from_str::<toml::value::Table>(
+            r#"generators = [
+                { name = "skip_grams", min_length = 1, max_skips = 1 },
+                { name = "abbreviations" },
+                { name = "suffix", suffix = "spp" },
+            ]"#,
+        )
+        .unwrap()["generators"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_table().unwrap().clone())
+            .collect();
+        let generators = registry.build_all(&configs).unwrap();
+        assert_eq!(
+            generators.iter().map(|g| g.name()).collect::<Vec<_>>(),
+            vec!["skip_grams", "abbreviations", "suffix"]
+        );
+        assert!(registry
+            .build(&toml::from_str("name = \"unknown\"").unwrap())
+            .is_err());
+
+        let mut tree = HashMapSearchTree::default();
+        tree.load_with_generators(
+            vec![(
+                String::from("Sula bassana"),
+                String::from("uri:sula_bassana"),
+            )],
+            &generators,
+        );
+        let types = |text: &str| -> Vec<MatchType> {
+            tree.search(text, None, None)
+                .into_iter()
+                .flat_map(|result| result.1.into_iter().map(|mtch| mtch.match_type))
+                .collect()
+        };
+        assert_eq!(types("Sula"), vec![MatchType::SkipGram]);
+        assert_eq!(types("S. bassana"), vec![MatchType::Abbreviated]);
+        assert_eq!(types("Sula bassana spp."), vec![MatchType::None]);
+    }
+
+    #[test]
+    fn test_taxonomic_names() {
+        let generator = TaxonomicNameGenerator::default();
+        let tokenizer = Tokenizer::default();
+        let generate = |term: &str| -> Vec<(String, MatchType)> {
+            generator
+                .generate(term, &tokenizer.tokenize(term).0, &tokenizer)
+                .into_iter()
+                .map(|(segments, match_type)| (segments.join(" "), match_type))
+                .collect()
+        };
+
+        assert_eq!(
+            generate("Puffinus puffinus (Brünnich, 1764)"),
+            vec![
+                (String::from("puffinus puffinus"), MatchType::Canonical),
+                (String::from("p puffinus"), MatchType::TaxonAbbreviated),
+            ]
+        );
+        assert_eq!(
+            generate("Homo sapiens subsp. sapiens"),
+            vec![
+                (
+                    String::from("homo sapiens ssp sapiens"),
+                    MatchType::Canonical
+                ),
+                (String::from("homo sapiens sapiens"), MatchType::Trinomial),
+                (String::from("homo sapiens"), MatchType::Binomial),
+                (
+                    String::from("h sapiens sapiens"),
+                    MatchType::TaxonAbbreviated
+                ),
+                (String::from("h s sapiens"), MatchType::TaxonAbbreviated),
+            ]
+        );
+        assert_eq!(
+            generate("Puffinus puffinus puffinus Brünnich, 1764")[..2],
+            [
+                (
+                    String::from("puffinus puffinus puffinus"),
+                    MatchType::Canonical
+                ),
+                (String::from("puffinus puffinus"), MatchType::Binomial),
+            ]
+        );
+        assert_eq!(
+            generate("Carex ssp"),
+            vec![(String::from("carex"), MatchType::Canonical),]
+        );
+        assert_eq!(
+            generate("Quercus robur Linnaeus"),
+            vec![
+                (String::from("quercus robur"), MatchType::Canonical),
+                (String::from("q robur"), MatchType::TaxonAbbreviated),
+            ]
+        );
+        assert_eq!(
+            generate("Bellis perennis L"),
+            vec![
+                (String::from("bellis perennis"), MatchType::Canonical),
+                (String::from("b perennis"), MatchType::TaxonAbbreviated),
+            ]
+        );
+        assert!(generate("Art").is_empty());
+        assert!(generate("Großer Sturmtaucher").is_empty());
+    }
+
+    #[test]
+    fn test_inflections() {
+        let rules = InflectionGenerator::read_rules("resources/inflection_de.tsv").unwrap();
+        let generator = InflectionGenerator::new(rules, 32);
+        let tokenizer = Tokenizer::default();
+        let generate = |term: &str| -> Vec<String> {
+            generator
+                .generate(term, &tokenizer.tokenize(term).0, &tokenizer)
+                .into_iter()
+                .map(|(segments, match_type)| {
+                    assert_eq!(match_type, MatchType::Inflected);
+                    segments.join(" ")
+                })
+                .collect()
+        };
+
+        assert_eq!(generate("Basstölpel"), vec!["basstölpels", "basstölpeln"]);
+        let variants = generate("Großer Sturmtaucher");
+        assert!(variants.contains(&String::from("großen sturmtauchers")));
+        assert!(variants.contains(&String::from("großer sturmtauchers")));
+        assert!(!variants.contains(&String::from("großer sturmtaucher")));
+        assert!(generate("1764").is_empty());
+    }
+}